@@ -22,7 +22,41 @@ impl AABB {
         )
     }
 
-    pub fn hit(&self, ray: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
+    // An inverted box, surrounding it with any box gives that box.
+    pub fn empty() -> Self {
+        Self::new(
+            Point3d::only(f64::INFINITY),
+            Point3d::only(f64::NEG_INFINITY)
+        )
+    }
+
+    #[inline]
+    pub fn centroid(&self) -> Point3d {
+        (self.minimum + self.maximum) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            0.0
+        } else {
+            2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+        }
+    }
+
+    #[inline]
+    pub fn longest_axis(&self) -> usize {
+        let d = self.maximum - self.minimum;
+        if d.x >= d.y && d.x >= d.z { 0 } else if d.y >= d.z { 1 } else { 2 }
+    }
+
+    #[inline]
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit_distance(ray, t_min, t_max).is_some()
+    }
+
+    // The distance where the ray enters the box, clamped to `t_min`.
+    pub fn hit_distance(&self, ray: &Ray, mut t_min: f64, mut t_max: f64) -> Option<f64> {
         macro_rules! test_on_axis {
             ($axis: ident) => {
                 let inv_d = 1.0 / ray.direction().$axis;
@@ -33,7 +67,7 @@ impl AABB {
                 t_min = t0.max(t_min);
                 t_max = t1.min(t_max);
                 if t_max <= t_min {
-                    return false
+                    return None
                 }
             };
         }
@@ -41,7 +75,7 @@ impl AABB {
         test_on_axis!(x);
         test_on_axis!(y);
         test_on_axis!(z);
-        true
+        Some(t_min)
     }
 
     #[inline]
@@ -49,6 +83,14 @@ impl AABB {
         box0.surround_with(&box1)
     }
 
+    #[inline]
+    pub fn surround_point(&self, point: Point3d) -> Self {
+        Self::new(
+            Point3d::element_wise_min(self.minimum, point),
+            Point3d::element_wise_max(self.maximum, point)
+        )
+    }
+
     #[inline]
     pub fn surround_with(&self, box1: &Self) -> Self {
         let small = Point3d::element_wise_min(
@@ -1,142 +1,479 @@
 use crate::acceleration::aabb::AABB;
-use crate::hittable_list::HittableList;
 use crate::hittable::{Hittable, HitRecord};
 use crate::ray::Ray;
-use std::borrow::Borrow;
+use crate::vec3::Point3d;
 use crate::util::random_in_range;
 use std::cmp::Ordering;
 
-pub struct BVH<'a> {
-    objects: &'a [Box<dyn Hittable + Send + Sync>],
-    root: Box<BVHTree>
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SplitMethod {
+    // Binned surface area heuristic, the best axis and plane is chosen for every node.
+    SurfaceAreaHeuristic,
+    // Sorts the leaves once along a random axis and splits every node at the median.
+    Median
 }
 
-impl<'a> BVH<'a> {
-    pub fn new(list: &'a [Box<dyn Hittable + Send + Sync>], t_min: f64, t_max: f64) -> Self {
-        let leaves = Self::get_leaves(list, t_min, t_max);
-        let root = Self::recursive_build(&leaves);
+#[derive(Copy, Clone, Debug)]
+pub struct BuildOptions {
+    pub split_method: SplitMethod,
+    pub max_leaf_size: usize,
+    pub bin_count: usize
+}
 
-        BVH {
-            objects: list,
-            root
+impl BuildOptions {
+    pub fn median() -> Self {
+        Self {
+            split_method: SplitMethod::Median,
+            max_leaf_size: 1,
+            ..Self::default()
         }
     }
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            split_method: SplitMethod::SurfaceAreaHeuristic,
+            max_leaf_size: 4,
+            bin_count: 12
+        }
+    }
+}
+
+// Cost of visiting a branch relative to intersecting one object.
+pub(super) const TRAVERSAL_COST: f64 = 0.125;
+// Below this depth nodes are split at the median, which bounds the depth of any tree to
+// MAX_SAH_DEPTH + log2(n) and lets the traversal use a fixed size stack.
+const MAX_SAH_DEPTH: usize = 32;
+pub(super) const STACK_SIZE: usize = 64;
 
-    fn get_leaves(list: &[Box<dyn Hittable + Send + Sync>], t_min: f64, t_max: f64) -> Vec<BVHTree> {
-        let mut leaves: Vec<_> = list.iter().enumerate().map(|(i, obj)| {
-            BVHTree::Leaf(obj.bounding_box(t_min, t_max).unwrap().clone(), i)
+#[derive(Clone)]
+struct Primitive {
+    bounds: AABB,
+    centroid: Point3d,
+    index: usize
+}
+
+// The tree and the order of objects it refers to, leaves own a range of `order`.
+pub(super) struct BVHBuild {
+    pub(super) root: Box<BVHTree>,
+    pub(super) order: Vec<usize>
+}
+
+impl BVHBuild {
+    pub(super) fn new(list: &[Box<dyn Hittable + Send + Sync>], t_min: f64, t_max: f64, options: BuildOptions) -> Self {
+        let mut primitives: Vec<_> = list.iter().enumerate().map(|(index, obj)| {
+            let bounds = obj.bounding_box(t_min, t_max).unwrap();
+            Primitive { centroid: bounds.centroid(), bounds, index }
         }).collect();
-        let axis = random_in_range(0, 2);
-        leaves.sort_unstable_by(|a, b| {
-            let box_a = a.bounds();
-            let box_b = b.bounds();
+        assert!(!primitives.is_empty(), "Cannot build a BVH without objects.");
 
-            box_a.minimum[axis].partial_cmp(&box_b.minimum[axis])
-                .unwrap_or(Ordering::Equal)
-        });
+        let root = match options.split_method {
+            SplitMethod::SurfaceAreaHeuristic => Self::build_sah(&mut primitives, 0, 0, &options),
+            SplitMethod::Median => {
+                let axis = random_in_range(0, 2);
+                primitives.sort_unstable_by(|a, b| {
+                    a.bounds.minimum[axis].partial_cmp(&b.bounds.minimum[axis])
+                        .unwrap_or(Ordering::Equal)
+                });
+                Self::build_median(&primitives, 0, axis, &options)
+            }
+        };
 
-        leaves
+        Self {
+            root,
+            order: primitives.iter().map(|primitive| primitive.index).collect()
+        }
+    }
+
+    fn leaf(primitives: &[Primitive], first: usize) -> Box<BVHTree> {
+        let bounds = primitives.iter()
+            .fold(AABB::empty(), |bounds, primitive| bounds.surround_with(&primitive.bounds));
+        Box::new(BVHTree::Leaf { bounds, first, count: primitives.len() })
     }
 
-    fn recursive_build(list: &[BVHTree]) -> Box<BVHTree> {
-        let object_span = list.len();
-        if object_span == 1 {
-            Box::new(list[0].clone())
+    fn branch(left: Box<BVHTree>, right: Box<BVHTree>, axis: usize) -> Box<BVHTree> {
+        let bounds = left.bounds().surround_with(right.bounds());
+        Box::new(BVHTree::Branch { left, right, bounds, axis })
+    }
+
+    // The primitives must be sorted along `axis`.
+    fn build_median(primitives: &[Primitive], first: usize, axis: usize, options: &BuildOptions) -> Box<BVHTree> {
+        if primitives.len() <= options.max_leaf_size.max(1) {
+            Self::leaf(primitives, first)
         } else {
-            let mid = list.len() >> 1;
-            let left = Self::recursive_build(&list[..mid]);
-            let right = Self::recursive_build(&list[mid..]);
-            let bounds = left.bounds().surround_with(right.bounds());
-
-            Box::new(BVHTree::Branch {
-                left, right, bounds
-            })
+            let mid = primitives.len() >> 1;
+            let left = Self::build_median(&primitives[..mid], first, axis, options);
+            let right = Self::build_median(&primitives[mid..], first + mid, axis, options);
+
+            Self::branch(left, right, axis)
         }
     }
 
-    fn merge_hits(ray: &Ray, objects: &'a [Box<dyn Hittable + Send + Sync>], candidates: &[usize], t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
-        candidates.iter().flat_map(|&index| {
-            objects[index].hit(ray, t_min, t_max)
-        }).min_by(|hit1, hit2| {
-            hit1.t.partial_cmp(&hit2.t).unwrap_or(Ordering::Equal)
-        })
+    fn build_sah(primitives: &mut [Primitive], first: usize, depth: usize, options: &BuildOptions) -> Box<BVHTree> {
+        let count = primitives.len();
+        if count == 1 {
+            return Self::leaf(primitives, first);
+        }
+
+        let bounds = primitives.iter()
+            .fold(AABB::empty(), |bounds, primitive| bounds.surround_with(&primitive.bounds));
+        let centroid_bounds = primitives.iter()
+            .fold(AABB::empty(), |bounds, primitive| bounds.surround_point(primitive.centroid));
+
+        if depth >= MAX_SAH_DEPTH {
+            let axis = centroid_bounds.longest_axis();
+            primitives.sort_unstable_by(|a, b| {
+                a.centroid[axis].partial_cmp(&b.centroid[axis]).unwrap_or(Ordering::Equal)
+            });
+            return Self::build_median(primitives, first, axis, options);
+        }
+
+        let (mid, axis) = match Self::find_sah_split(primitives, &bounds, &centroid_bounds, options) {
+            Some((cost, axis, split_bin)) => {
+                if count <= options.max_leaf_size && cost >= count as f64 {
+                    return Self::leaf(primitives, first);
+                }
+                let bin_count = options.bin_count.max(2);
+                let mid = partition(primitives, |primitive| {
+                    bin_index(primitive, axis, &centroid_bounds, bin_count) <= split_bin
+                });
+                (mid, axis)
+            }
+            // All centroids are at the same place, no plane can separate them.
+            None if count <= options.max_leaf_size => return Self::leaf(primitives, first),
+            None => (count >> 1, 0)
+        };
+
+        let (left, right) = primitives.split_at_mut(mid);
+        let left = Self::build_sah(left, first, depth + 1, options);
+        let right = Self::build_sah(right, first + mid, depth + 1, options);
+
+        Self::branch(left, right, axis)
+    }
+
+    // Returns the cost, the axis and the last bin on the left of the best splitting plane.
+    fn find_sah_split(
+        primitives: &[Primitive],
+        bounds: &AABB,
+        centroid_bounds: &AABB,
+        options: &BuildOptions
+    ) -> Option<(f64, usize, usize)> {
+        let bin_count = options.bin_count.max(2);
+        let area = bounds.surface_area().max(f64::EPSILON);
+        let mut best: Option<(f64, usize, usize)> = None;
+
+        for axis in 0..3 {
+            if centroid_bounds.maximum[axis] <= centroid_bounds.minimum[axis] {
+                continue;
+            }
+
+            let mut bins = vec![(0usize, AABB::empty()); bin_count];
+            for primitive in primitives {
+                let (count, bin_bounds) = &mut bins[bin_index(primitive, axis, centroid_bounds, bin_count)];
+                *count += 1;
+                *bin_bounds = bin_bounds.surround_with(&primitive.bounds);
+            }
+
+            // Sweep from the right to get the cost of the right side of every plane in one pass.
+            let mut right_costs = vec![0.0; bin_count];
+            let (mut right_count, mut right_bounds) = (0, AABB::empty());
+            for i in (1..bin_count).rev() {
+                right_count += bins[i].0;
+                right_bounds = right_bounds.surround_with(&bins[i].1);
+                right_costs[i] = right_count as f64 * right_bounds.surface_area();
+            }
+
+            let (mut left_count, mut left_bounds) = (0, AABB::empty());
+            for i in 0..bin_count - 1 {
+                left_count += bins[i].0;
+                left_bounds = left_bounds.surround_with(&bins[i].1);
+                if left_count == 0 || left_count == primitives.len() {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST +
+                    (left_count as f64 * left_bounds.surface_area() + right_costs[i + 1]) / area;
+                if !matches!(best, Some((best_cost, ..)) if best_cost <= cost) {
+                    best = Some((cost, axis, i));
+                }
+            }
+        }
+
+        best
     }
 }
 
+#[inline]
+fn bin_index(primitive: &Primitive, axis: usize, centroid_bounds: &AABB, bin_count: usize) -> usize {
+    let min = centroid_bounds.minimum[axis];
+    let extent = centroid_bounds.maximum[axis] - min;
+
+    (((primitive.centroid[axis] - min) / extent * bin_count as f64) as usize).min(bin_count - 1)
+}
+
+// Moves the elements satisfying the predicate to the front, returns how many there are.
+fn partition<T, F: Fn(&T) -> bool>(list: &mut [T], predicate: F) -> usize {
+    let mut mid = 0;
+    for i in 0..list.len() {
+        if predicate(&list[i]) {
+            list.swap(i, mid);
+            mid += 1;
+        }
+    }
+
+    mid
+}
+
 #[derive(Clone)]
-enum BVHTree {
-    Leaf(AABB, usize),
+pub(super) enum BVHTree {
+    Leaf {
+        bounds: AABB,
+        first: usize,
+        count: usize
+    },
     Branch {
         bounds: AABB,
         left: Box<BVHTree>,
-        right: Box<BVHTree>
+        right: Box<BVHTree>,
+        axis: usize
     }
 }
 
 impl BVHTree {
-    fn bounds(&self) -> &AABB {
+    pub(super) fn bounds(&self) -> &AABB {
         match self {
-            Self::Leaf(bounds, ..) => bounds,
+            Self::Leaf { bounds, .. } => bounds,
             Self::Branch { bounds, .. } => bounds
         }
     }
 
-    fn get_intersect_candidates(&self, ray: &Ray, t_min: f64, t_max: f64, candidates: &mut Vec<usize>) {
+    // Surface area heuristic cost of this subtree, scaled by the area of the root.
+    pub(super) fn cost(&self) -> f64 {
         match self {
-            Self::Leaf(bounds, index) => {
-                if bounds.hit(ray, t_min, t_max) {
-                    candidates.push(*index)
-                }
-            },
-            Self::Branch { bounds, left, right } => {
-                if bounds.hit(ray, t_min, t_max) {
-                    left.get_intersect_candidates(ray, t_min, t_max, candidates);
-                    right.get_intersect_candidates(ray, t_min, t_max, candidates);
-                }
-            }
+            Self::Leaf { bounds, count, .. } => bounds.surface_area() * *count as f64,
+            Self::Branch { bounds, left, right, .. } =>
+                bounds.surface_area() * TRAVERSAL_COST + left.cost() + right.cost()
         }
     }
-}
 
-impl<'a> Hittable for BVH<'a> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut candidates = vec![];
-        self.root.get_intersect_candidates(ray, t_min, t_max, &mut candidates);
+    // Front to back traversal, the nearer child is visited first and boxes farther than
+    // the closest hit so far are skipped.
+    fn closest_hit<'a>(
+        &self,
+        objects: &'a [Box<dyn Hittable + Send + Sync>],
+        order: &[usize],
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64
+    ) -> Option<HitRecord<'a>> {
+        let mut closest = t_max;
+        let mut hit = None;
+        let mut stack: [(&BVHTree, f64); STACK_SIZE] = [(self, t_min); STACK_SIZE];
+        let mut stack_size = 0;
+        let mut node = self;
+        self.bounds().hit_distance(ray, t_min, t_max)?;
 
-        Self::merge_hits(ray, self.objects, &candidates, t_min, t_max)
-    }
+        loop {
+            match node {
+                Self::Leaf { first, count, .. } => {
+                    for &index in &order[*first..*first + *count] {
+                        if let Some(record) = objects[index].hit(ray, t_min, closest) {
+                            closest = record.t;
+                            hit = Some(record);
+                        }
+                    }
+                }
+                Self::Branch { left, right, .. } => {
+                    let left_distance = left.bounds().hit_distance(ray, t_min, closest);
+                    let right_distance = right.bounds().hit_distance(ray, t_min, closest);
+                    match (left_distance, right_distance) {
+                        (Some(l), Some(r)) => {
+                            let (near, far, far_distance) =
+                                if l <= r { (left, right, r) } else { (right, left, l) };
+                            stack[stack_size] = (far.as_ref(), far_distance);
+                            stack_size += 1;
+                            node = near.as_ref();
+                            continue
+                        }
+                        (Some(_), None) => { node = left.as_ref(); continue }
+                        (None, Some(_)) => { node = right.as_ref(); continue }
+                        (None, None) => {}
+                    }
+                }
+            }
 
-    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
-        Some(self.root.bounds().clone())
+            loop {
+                if stack_size == 0 {
+                    return hit
+                }
+                stack_size -= 1;
+                let (next, distance) = stack[stack_size];
+                if distance <= closest {
+                    node = next;
+                    break
+                }
+            }
+        }
     }
 }
 
 pub struct OwnedBVH {
     objects: Vec<Box<dyn Hittable + Send + Sync>>,
-    root: Box<BVHTree>
+    tree: BVHBuild
 }
 
 impl OwnedBVH {
     pub fn new(objects: Vec<Box<dyn Hittable + Send + Sync>>, t_min: f64, t_max: f64) -> Self {
-        let leaves = BVH::get_leaves(&objects, t_min, t_max);
-        let root = BVH::recursive_build(&leaves);
+        Self::with_options(objects, t_min, t_max, BuildOptions::default())
+    }
+
+    pub fn with_options(
+        objects: Vec<Box<dyn Hittable + Send + Sync>>,
+        t_min: f64,
+        t_max: f64,
+        options: BuildOptions
+    ) -> Self {
+        let tree = BVHBuild::new(&objects, t_min, t_max, options);
 
         OwnedBVH {
             objects,
-            root
+            tree
         }
     }
 }
 
 impl Hittable for OwnedBVH {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut candidates = vec![];
-        self.root.get_intersect_candidates(ray, t_min, t_max, &mut candidates);
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.tree.root.closest_hit(&self.objects, &self.tree.order, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(self.tree.root.bounds().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::linear_bvh::LinearBVH;
+    use crate::color::Color3d;
+    use crate::hittable_list::HittableList;
+    use crate::material::DiffuseLight;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidColor;
+    use crate::triangle::Triangle;
+    use crate::util::{random_double, random_range, seed_random};
+    use crate::vec3::Vec3d;
+    use crate::vec3d_extensions::RandomGen;
+
+    type Objects = Vec<Box<dyn Hittable + Send + Sync>>;
+
+    const T_MIN: f64 = 0.001;
+
+    // Every object glows with its own index, which tells which object a hit record belongs to.
+    fn tag(index: usize) -> DiffuseLight<SolidColor> {
+        DiffuseLight::new(SolidColor::new(Color3d::new(index as f64, 0.0, 0.0)))
+    }
+
+    fn object_index(record: &HitRecord) -> usize {
+        record.material.emitted(record, Vec3d::zero()).x as usize
+    }
+
+    // Spheres and triangles of all sizes spread over a cube, many of them overlapping.
+    fn scattered_objects() -> Objects {
+        seed_random(1, 0);
+        let mut objects: Objects = vec![];
+        for index in 0..400 {
+            let center = Vec3d::random_range(-10.0, 10.0);
+            if index % 2 == 0 {
+                objects.push(Box::new(Sphere::new(center, random_range(0.05, 1.5), tag(index))));
+            } else {
+                let size = random_range(0.1, 3.0);
+                let [p0, p1, p2] = [(); 3].map(|_| center + size * Vec3d::random_range(-1.0, 1.0));
+                objects.push(Box::new(Triangle::new(p0, p1, p2, tag(index))));
+            }
+        }
+        objects
+    }
+
+    // Spheres shrinking towards the origin, each sixteen times smaller than the last so that binning
+    // only ever separates the largest one and the surface area heuristic alone would build a tree as
+    // deep as there are spheres.
+    fn nested_objects() -> Objects {
+        (0..64).map(|index| {
+            let scale = 0.0625f64.powi(index as i32);
+            let sphere: Box<dyn Hittable + Send + Sync> =
+                Box::new(Sphere::new(Vec3d::new(scale, 0.0, 0.0), 0.4 * scale, tag(index)));
+            sphere
+        }).collect()
+    }
+
+    fn scattered_ray() -> Ray {
+        let origin = Vec3d::random_range(-15.0, 15.0);
+        let target = Vec3d::random_range(-10.0, 10.0);
+        Ray::new(origin, target - origin)
+    }
+
+    // Rays from about as far as the size of the spheres they aim at, or the intersection of the
+    // smallest spheres is lost to rounding.
+    fn nested_ray() -> Ray {
+        let x = 0.0625f64.powf(random_range(0.0, 64.0));
+        let target = Vec3d::new(x, random_range(-0.4, 0.4) * x, random_range(-0.4, 0.4) * x);
+        let origin = target + 5.0 * x * Vec3d::random_unit_vector();
+        Ray::new(origin, target - origin)
+    }
+
+    fn assert_same_hit(name: &str, ray: &Ray, expected: &Option<HitRecord<'_>>, actual: Option<HitRecord<'_>>) {
+        match (expected, actual) {
+            (None, None) => {}
+            (Some(expected), Some(actual)) => {
+                assert_eq!(actual.t, expected.t, "{} on {:?}", name, ray);
+                assert_eq!(object_index(&actual), object_index(expected), "{} on {:?}", name, ray);
+            }
+            (expected, actual) => panic!(
+                "{} on {:?}: expected a hit {}, got {}", name, ray, expected.is_some(), actual.is_some()
+            )
+        }
+    }
+
+    // Returns the number of rays that hit something and the depth of the tree.
+    fn compare_with_list(objects: fn() -> Objects, ray: fn() -> Ray, options: BuildOptions) -> (usize, usize) {
+        let list = HittableList { objects: objects() };
+        let owned = OwnedBVH::with_options(objects(), 0.0, 1.0, options);
+        let linear = LinearBVH::new(&list.objects, 0.0, 1.0, options);
+
+        seed_random(2, 0);
+        let mut hits = 0;
+        for _ in 0..5000 {
+            let ray = ray();
+            let t_max = if random_double() < 0.2 { random_range(1.0, 10.0) } else { f64::INFINITY };
+            let expected = list.hit(&ray, T_MIN, t_max);
+            hits += expected.is_some() as usize;
+            assert_same_hit("owned", &ray, &expected, owned.hit(&ray, T_MIN, t_max));
+            assert_same_hit("linear", &ray, &expected, linear.hit(&ray, T_MIN, t_max));
+        }
+        (hits, linear.stats().depth)
+    }
+
+    #[test]
+    fn sah_finds_the_same_hits_as_a_list() {
+        let (hits, _) = compare_with_list(scattered_objects, scattered_ray, BuildOptions::default());
+        assert!(hits > 1000, "{} hits", hits);
+    }
 
-        BVH::merge_hits(ray, &self.objects, &candidates, t_min, t_max)
+    #[test]
+    fn median_finds_the_same_hits_as_a_list() {
+        let (hits, _) = compare_with_list(scattered_objects, scattered_ray, BuildOptions::median());
+        assert!(hits > 1000, "{} hits", hits);
     }
 
-    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
-        Some(self.root.bounds().clone())
+    #[test]
+    fn deep_sah_falls_back_to_median_and_finds_the_same_hits_as_a_list() {
+        let options = BuildOptions { max_leaf_size: 1, ..BuildOptions::default() };
+        let (hits, depth) = compare_with_list(nested_objects, nested_ray, options);
+        assert!(hits > 1000, "{} hits", hits);
+        // Only median splits go past MAX_SAH_DEPTH, the 32 spheres left fit in 6 more levels.
+        assert!(depth > MAX_SAH_DEPTH + 1 && depth <= MAX_SAH_DEPTH + 7, "depth {}", depth);
     }
 }
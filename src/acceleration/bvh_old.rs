@@ -89,7 +89,7 @@ impl<'a> BVH<'a> {
 }
 
 impl Hittable for BVH<'_> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         if !self.get_box().hit(ray, t_min, t_max) {
             None
         } else {
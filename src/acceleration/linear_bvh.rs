@@ -0,0 +1,213 @@
+use std::fmt::{Display, Formatter};
+
+use crate::acceleration::aabb::AABB;
+use crate::acceleration::bvh::{BVHBuild, BVHTree, BuildOptions, STACK_SIZE};
+use crate::hittable::{Hittable, HitRecord};
+use crate::ray::Ray;
+use crate::vec3::{Point3d, Vec3d};
+
+// A node of a flattened BVH. Nodes are stored in depth first order, so the first child of a
+// branch is the next node and only the offset of the second child is stored.
+// Bounds are rounded outwards to f32 to fit two nodes in a cache line.
+#[derive(Copy, Clone)]
+#[repr(C, align(32))]
+struct LinearNode {
+    minimum: [f32; 3],
+    maximum: [f32; 3],
+    // First object in `order` for leaves, index of the second child for branches.
+    offset: u32,
+    // Zero for branches.
+    count: u16,
+    axis: u8
+}
+
+const _: () = assert!(std::mem::size_of::<LinearNode>() == 32);
+
+fn round_down(x: f64) -> f32 {
+    let rounded = x as f32;
+    if rounded as f64 > x { next_toward_negative(rounded) } else { rounded }
+}
+
+fn round_up(x: f64) -> f32 {
+    let rounded = x as f32;
+    if (rounded as f64) < x { -next_toward_negative(-rounded) } else { rounded }
+}
+
+fn next_toward_negative(x: f32) -> f32 {
+    if x.is_nan() || x == f32::NEG_INFINITY {
+        x
+    } else if x == 0.0 {
+        -f32::from_bits(1)
+    } else if x > 0.0 {
+        f32::from_bits(x.to_bits() - 1)
+    } else {
+        f32::from_bits(x.to_bits() + 1)
+    }
+}
+
+impl LinearNode {
+    fn new(bounds: &AABB, offset: usize, count: usize, axis: usize) -> Self {
+        let AABB { minimum, maximum } = bounds;
+        Self {
+            minimum: [round_down(minimum.x), round_down(minimum.y), round_down(minimum.z)],
+            maximum: [round_up(maximum.x), round_up(maximum.y), round_up(maximum.z)],
+            offset: offset as u32,
+            count: count as u16,
+            axis: axis as u8
+        }
+    }
+
+    fn bounds(&self) -> AABB {
+        let [x0, y0, z0] = self.minimum;
+        let [x1, y1, z1] = self.maximum;
+        AABB::new(
+            Point3d::new(x0 as f64, y0 as f64, z0 as f64),
+            Point3d::new(x1 as f64, y1 as f64, z1 as f64)
+        )
+    }
+
+    #[inline]
+    fn hit(&self, origin: &Point3d, inv_direction: &Vec3d, mut t_min: f64, mut t_max: f64) -> bool {
+        for axis in 0..3 {
+            let t0 = (self.minimum[axis] as f64 - origin[axis]) * inv_direction[axis];
+            let t1 = (self.maximum[axis] as f64 - origin[axis]) * inv_direction[axis];
+            t_min = t0.min(t1).max(t_min);
+            t_max = t0.max(t1).min(t_max);
+            if t_max <= t_min {
+                return false
+            }
+        }
+
+        true
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BVHStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub depth: usize,
+    pub memory: usize,
+    pub cost: f64
+}
+
+impl Display for BVHStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} nodes ({} leaves), depth {}, {:.1}KiB, expected cost {:.2}",
+               self.node_count, self.leaf_count, self.depth,
+               self.memory as f64 / 1024.0, self.cost)
+    }
+}
+
+pub struct LinearBVH<'a> {
+    objects: &'a [Box<dyn Hittable + Send + Sync>],
+    nodes: Vec<LinearNode>,
+    order: Vec<usize>,
+    depth: usize,
+    cost: f64
+}
+
+impl<'a> LinearBVH<'a> {
+    pub fn new(
+        list: &'a [Box<dyn Hittable + Send + Sync>],
+        t_min: f64,
+        t_max: f64,
+        options: BuildOptions
+    ) -> Self {
+        let BVHBuild { root, order } = BVHBuild::new(list, t_min, t_max, options);
+        assert!(list.len() <= u32::MAX as usize, "Too many objects for a linear BVH.");
+
+        let mut nodes = vec![];
+        let depth = Self::flatten(&root, &mut nodes);
+        let cost = root.cost() / root.bounds().surface_area().max(f64::EPSILON);
+
+        Self {
+            objects: list,
+            nodes,
+            order,
+            depth,
+            cost
+        }
+    }
+
+    // Appends the subtree in depth first order, returns its depth.
+    fn flatten(tree: &BVHTree, nodes: &mut Vec<LinearNode>) -> usize {
+        match tree {
+            BVHTree::Leaf { bounds, first, count } => {
+                assert!(*count <= u16::MAX as usize, "Too many objects in a BVH leaf.");
+                nodes.push(LinearNode::new(bounds, *first, *count, 0));
+                1
+            }
+            BVHTree::Branch { bounds, left, right, axis } => {
+                let index = nodes.len();
+                nodes.push(LinearNode::new(bounds, 0, 0, *axis));
+                let left_depth = Self::flatten(left, nodes);
+                nodes[index].offset = nodes.len() as u32;
+                let right_depth = Self::flatten(right, nodes);
+
+                1 + left_depth.max(right_depth)
+            }
+        }
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        BVHStats {
+            node_count: self.nodes.len(),
+            leaf_count: self.nodes.iter().filter(|node| node.count > 0).count(),
+            depth: self.depth,
+            memory: self.nodes.len() * std::mem::size_of::<LinearNode>() +
+                self.order.len() * std::mem::size_of::<usize>(),
+            cost: self.cost
+        }
+    }
+}
+
+impl<'a> Hittable for LinearBVH<'a> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let origin = ray.origin();
+        let direction = ray.direction();
+        let inv_direction = Vec3d::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+
+        let mut closest = t_max;
+        let mut hit = None;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_size = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            if node.hit(&origin, &inv_direction, t_min, closest) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for &index in &self.order[first..first + node.count as usize] {
+                        if let Some(record) = self.objects[index].hit(ray, t_min, closest) {
+                            closest = record.t;
+                            hit = Some(record);
+                        }
+                    }
+                } else {
+                    // Visit the child on the side the ray comes from first.
+                    let (near, far) = if direction[node.axis as usize] < 0.0 {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[stack_size] = far as u32;
+                    stack_size += 1;
+                    current = near;
+                    continue
+                }
+            }
+
+            if stack_size == 0 {
+                return hit
+            }
+            stack_size -= 1;
+            current = stack[stack_size] as usize;
+        }
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(self.nodes[0].bounds())
+    }
+}
@@ -1,2 +1,3 @@
 pub(crate) mod aabb;
-pub mod bvh;
\ No newline at end of file
+pub mod bvh;
+pub mod linear_bvh;
@@ -0,0 +1,122 @@
+use crate::hittable::HitRecord;
+use crate::color::Color3d;
+use crate::vec3::Vec3d;
+use crate::texture::Texture;
+use crate::material::{Material, ScatterSample};
+use crate::vec3d_extensions::OrthonormalBasis;
+
+// Both wrappers only change the shading normal, the wrapped material sees a copy of the hit
+// record with the perturbed normal on the side that was hit.
+fn with_shading_normal<'a>(hit_record: &HitRecord<'a>, outward_normal: Vec3d) -> HitRecord<'a> {
+    let mut record = hit_record.clone();
+    record.normal = if record.front_face() { outward_normal } else { -outward_normal };
+    record
+}
+
+macro_rules! forward_material {
+    () => {
+        fn eval(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> Color3d {
+            self.material.eval(&self.shade(hit_record), wi, wo)
+        }
+
+        fn pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> f64 {
+            self.material.pdf(&self.shade(hit_record), wi, wo)
+        }
+
+        fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+            self.material.sample(&self.shade(hit_record), wo)
+        }
+
+        fn emitted(&self, hit_record: &HitRecord, wo: Vec3d) -> Color3d {
+            self.material.emitted(&self.shade(hit_record), wo)
+        }
+
+        fn is_emissive(&self) -> bool {
+            self.material.is_emissive()
+        }
+    }
+}
+
+// Tangent space normals stored as colors, red along dpdu, green along dpdv and blue along the
+// normal, each mapped from [0, 1] to [-1, 1]. The flat normal is (0.5, 0.5, 1).
+pub struct NormalMap<M: Material, T: Texture> {
+    pub material: M,
+    pub map: T
+}
+
+impl<M: Material, T: Texture> NormalMap<M, T> {
+    pub fn new(material: M, map: T) -> Self {
+        Self { material, map }
+    }
+
+    fn shade<'a>(&self, hit_record: &HitRecord<'a>) -> HitRecord<'a> {
+        let normal = hit_record.outward_normal();
+        let mut tangent = hit_record.dpdu - normal * normal.dot(&hit_record.dpdu);
+        if tangent.near_zero() {
+            tangent = OrthonormalBasis::from_w(normal).u;
+        }
+        let tangent = tangent.normalized();
+        let mut bitangent = normal.cross(&tangent);
+        if bitangent.dot(&hit_record.dpdv) < 0.0 {
+            bitangent = -bitangent;
+        }
+
+        let value = self.map.eval(hit_record.u, hit_record.v, hit_record.point) * 2.0 - Color3d::one();
+        let shading_normal = (tangent * value.x + bitangent * value.y + normal * value.z).normalized();
+        with_shading_normal(hit_record, shading_normal)
+    }
+}
+
+impl<M: Material, T: Texture> Material for NormalMap<M, T> {
+    forward_material!();
+}
+
+// Offset along u and v for the finite differences of the height.
+const BUMP_DELTA: f64 = 0.0005;
+
+// Moves the surface along its normal by the height from a scalar texture times `scale` and shades
+// with the normal of the moved surface. The geometry itself is left as it is. Heights are read
+// from the mean of the channels of the texture.
+pub struct BumpMap<M: Material, T: Texture> {
+    pub material: M,
+    pub height: T,
+    pub scale: f64
+}
+
+impl<M: Material, T: Texture> BumpMap<M, T> {
+    pub fn new(material: M, height: T, scale: f64) -> Self {
+        Self { material, height, scale }
+    }
+
+    fn displacement(&self, u: f64, v: f64, hit_record: &HitRecord, offset: Vec3d) -> f64 {
+        let value = self.height.eval(u, v, hit_record.point + offset);
+        self.scale * (value.x + value.y + value.z) / 3.0
+    }
+
+    fn shade<'a>(&self, hit_record: &HitRecord<'a>) -> HitRecord<'a> {
+        let (u, v) = (hit_record.u, hit_record.v);
+        let (dpdu, dpdv) = (hit_record.dpdu, hit_record.dpdv);
+        let normal = hit_record.outward_normal();
+
+        let displacement = self.displacement(u, v, hit_record, Vec3d::zero());
+        let displacement_u = self.displacement(u + BUMP_DELTA, v, hit_record, dpdu * BUMP_DELTA);
+        let displacement_v = self.displacement(u, v + BUMP_DELTA, hit_record, dpdv * BUMP_DELTA);
+
+        // Changes of the normal along the surface are left out.
+        let bumped_dpdu = dpdu + normal * ((displacement_u - displacement) / BUMP_DELTA);
+        let bumped_dpdv = dpdv + normal * ((displacement_v - displacement) / BUMP_DELTA);
+        let mut shading_normal = bumped_dpdu.cross(&bumped_dpdv);
+        if shading_normal.near_zero() {
+            return hit_record.clone()
+        }
+        // The tangents may be left-handed.
+        if (dpdu.cross(&dpdv)).dot(&normal) < 0.0 {
+            shading_normal = -shading_normal;
+        }
+        with_shading_normal(hit_record, shading_normal.normalized())
+    }
+}
+
+impl<M: Material, T: Texture> Material for BumpMap<M, T> {
+    forward_material!();
+}
@@ -9,7 +9,9 @@ pub struct Camera {
     vertical: Vec3d,
     u: Vec3d, v: Vec3d, w: Vec3d,
     lens_radius: f64,
-    pub shutter_open: f64, pub shutter_close: f64 // Shutter open/close time
+    pub shutter_open: f64, pub shutter_close: f64, // Shutter open/close time
+    // Kept to rebuild the camera when the image is resized.
+    look_at: Point3d, vup: Vec3d, fov: f64, focus_dist: f64
 }
 
 impl Camera {
@@ -68,10 +70,24 @@ impl Camera {
             lower_left_corner,
             u, v, w,
             lens_radius,
-            shutter_open, shutter_close
+            shutter_open, shutter_close,
+            look_at, vup, fov: theta, focus_dist
         }
     }
 
+    pub fn with_aspect_ratio(&self, aspect_ratio: f64) -> Self {
+        Self::new_with_shutter(
+            self.origin,
+            self.look_at,
+            self.vup,
+            aspect_ratio,
+            Angle::RadAngle(self.fov),
+            self.lens_radius * 2.0,
+            self.focus_dist,
+            self.shutter_open, self.shutter_close
+        )
+    }
+
     pub fn get_ray(&self, u: f64, v: f64) -> Ray {
         let radius = self.lens_radius * Vec3d::random_in_unit_disk();
         let offset = self.u * radius.x + self.v * radius.y;
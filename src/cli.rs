@@ -0,0 +1,230 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use image::ImageFormat;
+
+use crate::color::Color3d;
+use crate::presets::{SCENE_NAMES, DEFAULT_SCENE};
+use crate::acceleration::bvh::BuildOptions;
+use crate::tone_mapping::{ToneOperator, DEFAULT_WHITE, TONE_OPERATOR_NAMES};
+use crate::sampler::{SamplerType, SAMPLER_NAMES};
+use crate::scene::MAX_IMAGE_SIZE;
+
+pub const USAGE: &str = "\
+Usage: ray_tracing_rust [OPTIONS]
+
+Options:
+  -s, --scene <NAME|FILE>    Built-in scene name or path to a scene file [default: all_feature_box]
+  -o, --output <PATH>        Output image path, its extension picks the format [default: image.ppm]
+  -f, --format <FORMAT>      Output format: ppm, png, jpeg, bmp, tga, tiff, hdr or exr
+      --quality <N>          JPEG quality from 1 to 100 [default: 90]
+  -w, --width <PIXELS>       Image width, keeps the aspect ratio unless --height is given
+      --height <PIXELS>      Image height, keeps the aspect ratio unless --width is given
+      --spp <N>              Samples per pixel
+      --max-depth <N>        Maximum number of bounces of a ray
+      --background <R,G,B>   Background color, a single value for gray
+      --exposure <EV>        Exposure in stops before tone mapping
+      --tone-map <OPERATOR>  Tone mapping: clamp, reinhard, extended_reinhard, aces or agx
+      --white <LUMINANCE>    Brightness mapped to white by extended_reinhard, also of a scene file [default: 4]
+      --serial               Render on the current thread only
+      --verbose              Print the render times of the tiles
+      --sampler <NAME>       Sample points: independent, stratified, halton or sobol
+      --seed <N>             Seed of the random numbers, renders with the same seed match [default: 0]
+      --tile-size <PIXELS>   Width and height of the blocks rendered as one task [default: 32]
+      --bvh <METHOD>         BVH split method: sah or median [default: sah]
+      --list-scenes          Print the names of built-in scenes
+  -h, --help                 Print this message";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputFormat {
+    Ppm,
+    Jpeg,
+    // Linear colors in floating point, before gamma correction and clamping.
+    Hdr,
+    Exr,
+    Image(ImageFormat)
+}
+
+impl OutputFormat {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ppm" => Some(OutputFormat::Ppm),
+            "png" => Some(OutputFormat::Image(ImageFormat::Png)),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "bmp" => Some(OutputFormat::Image(ImageFormat::Bmp)),
+            "tga" => Some(OutputFormat::Image(ImageFormat::Tga)),
+            "tif" | "tiff" => Some(OutputFormat::Image(ImageFormat::Tiff)),
+            "hdr" => Some(OutputFormat::Hdr),
+            "exr" => Some(OutputFormat::Exr),
+            _ => None
+        }
+    }
+
+    fn from_path(path: &str) -> Option<Self> {
+        Path::new(path).extension()?.to_str().and_then(Self::parse)
+    }
+}
+
+pub struct RenderOptions {
+    pub scene: String,
+    pub output: String,
+    pub format: OutputFormat,
+    pub quality: u8,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub spp: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub background: Option<Color3d>,
+    pub exposure: Option<f64>,
+    pub tone_operator: Option<ToneOperator>,
+    // Applies to the extended Reinhard operator of the scene file too.
+    pub white: Option<f64>,
+    pub serial: bool,
+    pub verbose: bool,
+    pub tile_size: Option<usize>,
+    pub seed: Option<u64>,
+    pub sampler: Option<SamplerType>,
+    pub bvh_options: BuildOptions
+}
+
+pub enum Command {
+    Render(Box<RenderOptions>),
+    ListScenes,
+    Help
+}
+
+#[derive(Debug)]
+pub struct CliError(String);
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl Command {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Command, CliError> {
+        let mut format = None;
+        let mut options = RenderOptions {
+            scene: DEFAULT_SCENE.to_string(),
+            output: "image.ppm".to_string(),
+            format: OutputFormat::Ppm,
+            quality: 90,
+            width: None,
+            height: None,
+            spp: None,
+            max_depth: None,
+            background: None,
+            exposure: None,
+            tone_operator: None,
+            white: None,
+            serial: false,
+            verbose: false,
+            tile_size: None,
+            seed: None,
+            sampler: None,
+            bvh_options: BuildOptions::default()
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next()
+                .ok_or_else(|| CliError(format!("missing value for `{}`", arg)));
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "--list-scenes" => return Ok(Command::ListScenes),
+                "-s" | "--scene" => options.scene = value()?,
+                "-o" | "--output" => options.output = value()?,
+                "-f" | "--format" => {
+                    let name = value()?;
+                    format = Some(OutputFormat::parse(&name)
+                        .ok_or_else(|| CliError(format!("unknown output format `{}`", name)))?);
+                }
+                "--quality" => options.quality = match parse_positive(&arg, &value()?)? {
+                    n if n <= 100 => n as u8,
+                    _ => return Err(CliError(format!("`{}` must be at most 100", arg)))
+                },
+                "-w" | "--width" => options.width = Some(parse_dimension(&arg, &value()?)?),
+                "--height" => options.height = Some(parse_dimension(&arg, &value()?)?),
+                "--spp" => options.spp = Some(parse_positive(&arg, &value()?)?),
+                "--max-depth" => options.max_depth = Some(parse_positive(&arg, &value()?)?),
+                "--background" => options.background = Some(parse_color(&arg, &value()?)?),
+                "--exposure" => options.exposure = Some(parse_number(&arg, &value()?)?),
+                "--tone-map" => {
+                    let name = value()?;
+                    options.tone_operator = Some(ToneOperator::parse(&name, DEFAULT_WHITE).ok_or_else(|| CliError(format!(
+                        "unknown tone mapping `{}`, expected one of {}", name, TONE_OPERATOR_NAMES.join(", ")
+                    )))?);
+                }
+                "--white" => options.white = match parse_number(&arg, &value()?)? {
+                    n if n > 0.0 => Some(n),
+                    _ => return Err(CliError(format!("`{}` must be positive", arg)))
+                },
+                "--serial" => options.serial = true,
+                "--verbose" => options.verbose = true,
+                "--sampler" => {
+                    let name = value()?;
+                    options.sampler = Some(SamplerType::parse(&name).ok_or_else(|| CliError(format!(
+                        "unknown sampler `{}`, expected one of {}", name, SAMPLER_NAMES.join(", ")
+                    )))?);
+                }
+                "--seed" => options.seed = Some(value()?.parse()
+                    .map_err(|_| CliError(format!("`{}` expects a non-negative integer", arg)))?),
+                "--tile-size" => options.tile_size = Some(parse_positive(&arg, &value()?)?),
+                "--bvh" => options.bvh_options = match value()?.as_str() {
+                    "sah" => BuildOptions::default(),
+                    "median" => BuildOptions::median(),
+                    method => return Err(CliError(format!("unknown BVH split method `{}`", method)))
+                },
+                _ => return Err(CliError(format!("unknown argument `{}`", arg)))
+            }
+        }
+
+        // An explicit format wins over the extension of the output path.
+        options.format = match format.or_else(|| OutputFormat::from_path(&options.output)) {
+            Some(format) => format,
+            None => return Err(CliError(format!(
+                "unknown output format of `{}`, give it with `--format`", options.output
+            )))
+        };
+
+        Ok(Command::Render(Box::new(options)))
+    }
+}
+
+impl RenderOptions {
+    pub fn is_builtin_scene(&self) -> bool {
+        SCENE_NAMES.contains(&self.scene.as_str())
+    }
+}
+
+fn parse_positive(arg: &str, value: &str) -> Result<usize, CliError> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(CliError(format!("`{}` expects a positive integer, found `{}`", arg, value)))
+    }
+}
+
+fn parse_dimension(arg: &str, value: &str) -> Result<usize, CliError> {
+    match parse_positive(arg, value)? {
+        n if n < 2 => Err(CliError(format!("`{}` must be at least 2 pixels", arg))),
+        n if n > MAX_IMAGE_SIZE => Err(CliError(format!("`{}` can be at most {} pixels", arg, MAX_IMAGE_SIZE))),
+        n => Ok(n)
+    }
+}
+
+fn parse_number(arg: &str, value: &str) -> Result<f64, CliError> {
+    match value.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(n),
+        _ => Err(CliError(format!("`{}` expects a number, found `{}`", arg, value)))
+    }
+}
+
+fn parse_color(arg: &str, value: &str) -> Result<Color3d, CliError> {
+    let components: Result<Vec<f64>, _> = value.split(',').map(str::parse).collect();
+    match components.as_deref() {
+        Ok([gray]) => Ok(Color3d::only(*gray)),
+        Ok([r, g, b]) => Ok(Color3d::new(*r, *g, *b)),
+        _ => Err(CliError(format!("`{}` expects `r,g,b` or a single gray value, found `{}`", arg, value)))
+    }
+}
@@ -0,0 +1,102 @@
+// Piecewise constant distributions over [0, 1) and [0, 1)², used to importance sample tabulated
+// functions like the pixels of an environment map.
+
+pub struct Distribution1D {
+    function: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64
+}
+
+impl Distribution1D {
+    // `function` must not be empty and its values must not be negative.
+    pub fn new(function: Vec<f64>) -> Self {
+        let n = function.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for i in 0..n {
+            cdf.push(cdf[i] + function[i] / n as f64);
+        }
+
+        let integral = cdf[n];
+        if integral > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= integral);
+        } else {
+            // Sample uniformly when the function is zero everywhere.
+            cdf.iter_mut().enumerate().for_each(|(i, c)| *c = i as f64 / n as f64);
+        }
+
+        Self { function, cdf, integral }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.function.len()
+    }
+
+    #[inline]
+    fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // Maps a uniform sample to a point distributed like the function, also returns its density
+    // and the index of its segment.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // The last segment whose cdf is not greater than `u`.
+        let offset = self.cdf.partition_point(|&c| c <= u).saturating_sub(1).min(self.len() - 1);
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+
+        let x = ((offset as f64 + du) / self.len() as f64).min(1.0 - f64::EPSILON);
+        (x, self.pdf_at(offset), offset)
+    }
+
+    #[inline]
+    fn pdf_at(&self, offset: usize) -> f64 {
+        if self.integral > 0.0 { self.function[offset] / self.integral } else { 1.0 }
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        self.pdf_at(self.offset(x))
+    }
+
+    #[inline]
+    fn offset(&self, x: f64) -> usize {
+        ((x * self.len() as f64) as usize).min(self.len() - 1)
+    }
+}
+
+// Samples v from the marginal distribution of the rows, then u from the row.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D
+}
+
+impl Distribution2D {
+    // `function` holds `height` rows of `width` values.
+    pub fn new(function: &[f64], width: usize, height: usize) -> Self {
+        assert_eq!(function.len(), width * height, "Function does not match its dimensions.");
+        let conditional: Vec<_> = function.chunks(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(Distribution1D::integral).collect());
+
+        Self { conditional, marginal }
+    }
+
+    // Returns the point (u, v) and its density.
+    pub fn sample(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (v, marginal_pdf, row) = self.marginal.sample(u.1);
+        let (u, conditional_pdf, _) = self.conditional[row].sample(u.0);
+
+        ((u, v), marginal_pdf * conditional_pdf)
+    }
+
+    pub fn pdf(&self, (u, v): (f64, f64)) -> f64 {
+        let row = self.marginal.offset(v);
+        self.marginal.pdf_at(row) * self.conditional[row].pdf(u)
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::f64::consts::{PI, TAU};
+use image::{ImageError, ImageResult};
+use image::error::ImageFormatHint;
+use image::codecs::hdr::HdrDecoder;
+
+use crate::color::Color3d;
+use crate::tone_mapping::srgb_decode;
+use crate::vec3::Vec3d;
+use crate::util::{Angle, random_2d};
+use crate::distribution::Distribution2D;
+use crate::vec3d_extensions::{OrthonormalBasis, uniform_cone_pdf};
+
+// Light arriving from infinitely far away along rays that leave the scene.
+pub trait Environment {
+    // Radiance arriving from `direction`, which points away from the scene.
+    fn radiance(&self, direction: Vec3d) -> Color3d;
+
+    // Environments sampled by the integrator have to implement `pdf_value` and `random` as well.
+    fn is_sampled(&self) -> bool {
+        false
+    }
+
+    // Solid angle density of `random` choosing `direction`.
+    fn pdf_value(&self, _direction: Vec3d) -> f64 {
+        0.0
+    }
+
+    fn random(&self) -> Vec3d {
+        Vec3d::new(1.0, 0.0, 0.0)
+    }
+}
+
+// A constant background.
+impl Environment for Color3d {
+    fn radiance(&self, _direction: Vec3d) -> Color3d {
+        *self
+    }
+}
+
+#[inline]
+pub fn luminance(color: Color3d) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+// Blends from `bottom` straight down to `top` straight up, the sky of "Ray Tracing in One Weekend"
+// by default.
+pub struct GradientSky {
+    pub bottom: Color3d,
+    pub top: Color3d
+}
+
+impl GradientSky {
+    pub fn new(bottom: Color3d, top: Color3d) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Default for GradientSky {
+    fn default() -> Self {
+        Self::new(Color3d::one(), Color3d::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Environment for GradientSky {
+    fn radiance(&self, direction: Vec3d) -> Color3d {
+        let t = 0.5 * (direction.normalized().y + 1.0);
+        Color3d::linear_interpolation(self.bottom, self.top, t)
+    }
+}
+
+// Perez et al. luminance distribution, (1 + A e^(B / cos θ)) (1 + C e^(D γ) + E cos² γ).
+#[derive(Copy, Clone)]
+struct Perez([f64; 5]);
+
+impl Perez {
+    fn eval(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+// Clear sky model of Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight".
+// Directions below the horizon see the sky at the horizon. An optional sun disc is importance
+// sampled as a light.
+pub struct PreethamSky {
+    sun_direction: Vec3d,
+    // Perez functions and zenith values of the luminance Y and chromaticities x, y.
+    perez: [Perez; 3],
+    zenith: [f64; 3],
+    pub intensity: f64,
+    sun_radiance: Color3d,
+    cos_sun_radius: f64
+}
+
+// Brings the luminance of the model in kcd/m² to about 0.5 for a clear sky at noon.
+const SKY_SCALE: f64 = 0.05;
+
+impl PreethamSky {
+    // `sun_direction` points from the scene towards the sun, turbidity is between 2 (very clear)
+    // and 10 (hazy).
+    pub fn new(sun_direction: Vec3d, turbidity: f64) -> Self {
+        let sun_direction = sun_direction.normalized();
+        let t = turbidity;
+        // The model is not defined for the sun below the horizon.
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos();
+
+        let perez = [
+            Perez([0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703]),
+            Perez([-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452]),
+            Perez([-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529])
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let theta = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let row = |r: [f64; 4]| r.iter().zip(theta.iter()).map(|(a, b)| a * b).sum::<f64>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886]
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688]
+        ]);
+
+        // Zenith values are divided by the distribution at the zenith once here.
+        let zenith = [zenith_luminance, zenith_x, zenith_y];
+        let normalized = [0, 1, 2].map(|i| zenith[i] / perez[i].eval(1.0, theta_s));
+
+        Self {
+            sun_direction,
+            perez,
+            zenith: normalized,
+            intensity: 1.0,
+            sun_radiance: Color3d::zero(),
+            cos_sun_radius: 1.0
+        }
+    }
+
+    // Adds a sun disc of the given angular radius, `irradiance` is measured on a surface facing it.
+    pub fn with_sun(mut self, irradiance: Color3d, radius: Angle) -> Self {
+        self.cos_sun_radius = radius.rad().cos();
+        self.sun_radiance = irradiance / (2.0 * PI * (1.0 - self.cos_sun_radius));
+        self
+    }
+
+    #[inline]
+    fn has_sun(&self) -> bool {
+        self.cos_sun_radius < 1.0 && !self.sun_radiance.near_zero()
+    }
+
+    fn sky_radiance(&self, direction: Vec3d) -> Color3d {
+        let cos_theta = direction.y.max(0.001);
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| self.zenith[i] * self.perez[i].eval(cos_theta, gamma));
+
+        // Yxy to XYZ to linear sRGB.
+        let big_x = x / y * luminance;
+        let big_z = (1.0 - x - y) / y * luminance;
+        let rgb = Color3d::new(
+            3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+            -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+            0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z
+        );
+
+        Color3d::element_wise_max(rgb, Color3d::zero()) * SKY_SCALE
+    }
+}
+
+impl Environment for PreethamSky {
+    fn radiance(&self, direction: Vec3d) -> Color3d {
+        let direction = direction.normalized();
+        let sky = self.sky_radiance(direction) * self.intensity;
+        if self.has_sun() && direction.dot(&self.sun_direction) >= self.cos_sun_radius {
+            sky + self.sun_radiance
+        } else {
+            sky
+        }
+    }
+
+    fn is_sampled(&self) -> bool {
+        self.has_sun()
+    }
+
+    // Only the sun is sampled, the sky is smooth enough to be found by material sampling.
+    fn pdf_value(&self, direction: Vec3d) -> f64 {
+        if direction.normalized().dot(&self.sun_direction) >= self.cos_sun_radius {
+            uniform_cone_pdf(self.cos_sun_radius)
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self) -> Vec3d {
+        OrthonormalBasis::from_w(self.sun_direction).local(Vec3d::random_in_cone(self.cos_sun_radius))
+    }
+}
+
+// An equirectangular image around the scene, the top row is straight up (+y) and the
+// horizontal axis follows longitude like the uv coordinates of a sphere.
+// Directions are importance sampled proportionally to luminance.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color3d>,
+    rotation: f64,
+    intensity: f64,
+    distribution: Distribution2D
+}
+
+impl EnvironmentMap {
+    // Pixels are stored row by row from the top.
+    pub fn new(width: usize, height: usize, pixels: Vec<Color3d>, rotation: Angle, intensity: f64) -> Self {
+        assert_eq!(pixels.len(), width * height, "Pixels do not match the dimensions of the map.");
+        // Rows near the poles cover less solid angle.
+        let function: Vec<f64> = pixels.iter().enumerate().map(|(index, &pixel)| {
+            let theta = PI * ((index / width) as f64 + 0.5) / height as f64;
+            luminance(pixel) * theta.sin()
+        }).collect();
+        let distribution = Distribution2D::new(&function, width, height);
+
+        Self {
+            width, height, pixels,
+            rotation: rotation.rad(),
+            intensity,
+            distribution
+        }
+    }
+
+    // Radiance HDR files keep their values, other formats are sRGB encoded and decoded to linear
+    // [0, 1]. The image library has no OpenEXR decoder, EXR maps have to be converted to HDR first.
+    pub fn load<P: AsRef<Path>>(path: P, rotation: Angle, intensity: f64) -> ImageResult<Self> {
+        let path = path.as_ref();
+        let has_extension = |name: &str| {
+            matches!(path.extension(), Some(extension) if extension.eq_ignore_ascii_case(name))
+        };
+        let (width, height, pixels) = if has_extension("hdr") {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder.read_image_hdr()?.into_iter()
+                .map(|pixel| {
+                    let [r, g, b] = pixel.0;
+                    Color3d::new(r as f64, g as f64, b as f64)
+                })
+                .collect();
+            (metadata.width as usize, metadata.height as usize, pixels)
+        } else if has_extension("exr") {
+            return Err(ImageError::Unsupported(ImageFormatHint::Name("OpenEXR".to_string()).into()))
+        } else {
+            // 16 bits keep the precision of 16 bit files, 8 bit ones are scaled up.
+            let image = image::open(path)?.to_rgb16();
+            let pixels = image.pixels()
+                .map(|pixel| {
+                    let [r, g, b] = pixel.0;
+                    let decode = |x: u16| srgb_decode(x as f64 / 65535.0);
+                    Color3d::new(decode(r), decode(g), decode(b))
+                })
+                .collect();
+            (image.width() as usize, image.height() as usize, pixels)
+        };
+
+        Ok(Self::new(width, height, pixels, rotation, intensity))
+    }
+
+    // Coordinates in [0, 1)² of a unit direction.
+    fn direction_to_uv(&self, direction: Vec3d) -> (f64, f64) {
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+        let phi = (-direction.z).atan2(direction.x) + PI - self.rotation;
+        (phi.rem_euclid(TAU) / TAU, theta / PI)
+    }
+
+    fn uv_to_direction(&self, (u, v): (f64, f64)) -> Vec3d {
+        let theta = v * PI;
+        let phi = u * TAU + self.rotation - PI;
+        let sin_theta = theta.sin();
+        Vec3d::new(sin_theta * phi.cos(), theta.cos(), -sin_theta * phi.sin())
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: Vec3d) -> Color3d {
+        let (u, v) = self.direction_to_uv(direction.normalized());
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i] * self.intensity
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, direction: Vec3d) -> f64 {
+        let (u, v) = self.direction_to_uv(direction.normalized());
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0
+        }
+
+        // The map covers 2π by π radians of longitude and colatitude.
+        self.distribution.pdf((u, v)) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn random(&self) -> Vec3d {
+        let (uv, _) = self.distribution.sample(random_2d());
+        self.uv_to_direction(uv)
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::{self, Write};
+use crate::color::Color3d;
+
+// A minimal OpenEXR writer: single part, scanline, uncompressed, 32-bit float R, G and B.
+// See "The OpenEXR File Layout" for the meaning of the attributes.
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: u32 = 2;
+const PIXEL_TYPE_FLOAT: u32 = 2;
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn header(width: usize, height: usize) -> Vec<u8> {
+    // Channels are stored in alphabetical order.
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and three reserved bytes, then the sampling in x and y.
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&1u32.to_le_bytes());
+        channels.extend_from_slice(&1u32.to_le_bytes());
+    }
+    channels.push(0);
+
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter()
+        .flat_map(|n| n.to_le_bytes())
+        .collect();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[0]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+    header
+}
+
+// `pixels` are linear colors in rows from top to bottom.
+pub fn write_exr(fp: &mut impl Write, width: usize, height: usize, pixels: &[Color3d]) -> io::Result<()> {
+    let header = header(width, height);
+    fp.write_all(&header)?;
+
+    // Every scanline is a chunk of its y coordinate, its size and the channels one after another.
+    let chunk_size = 4 + 4 + 3 * 4 * width;
+    let first_chunk = header.len() + 8 * height;
+    for y in 0..height {
+        fp.write_all(&((first_chunk + y * chunk_size) as u64).to_le_bytes())?;
+    }
+
+    let mut chunk = Vec::with_capacity(chunk_size);
+    for (y, row) in pixels.chunks(width).take(height).enumerate() {
+        chunk.clear();
+        chunk.extend_from_slice(&(y as i32).to_le_bytes());
+        chunk.extend_from_slice(&((3 * 4 * width) as u32).to_le_bytes());
+        for channel in [2, 1, 0] {
+            for color in row {
+                chunk.extend_from_slice(&(color[channel] as f32).to_le_bytes());
+            }
+        }
+        fp.write_all(&chunk)?;
+    }
+
+    Ok(())
+}
@@ -4,6 +4,7 @@ use crate::material::Material;
 use crate::acceleration::aabb::AABB;
 use std::rc::Rc;
 use crate::hittable_list::HittableList;
+use crate::vec3d_extensions::OrthonormalBasis;
 
 #[derive(Clone)]
 pub struct HitRecord<'a> {
@@ -11,8 +12,14 @@ pub struct HitRecord<'a> {
     pub normal: Vec3d,
     pub t: f64,
     pub u: f64, pub v: f64,
+    // Partial derivatives of the point with respect to u and v, not necessarily unit length or
+    // perpendicular to each other. Shapes without a parameterization get any tangent frame.
+    pub dpdu: Vec3d,
+    pub dpdv: Vec3d,
     pub material: &'a (dyn Material + Send + Sync),
-    front_face: bool
+    front_face: bool,
+    // Length of the ray direction, `t` is measured in multiples of it.
+    ray_length: f64
 }
 
 impl<'a> HitRecord<'a> {
@@ -26,12 +33,40 @@ impl<'a> HitRecord<'a> {
     ) -> Self {
         let front_face = ray.direction().dot(&outward_normal) < 0.0;
         let normal = if front_face { outward_normal } else { -outward_normal };
+        let basis = OrthonormalBasis::from_w(outward_normal);
         Self {
-            t, point, normal, front_face, material, u, v
+            t, point, normal, front_face, material, u, v,
+            dpdu: basis.u,
+            dpdv: basis.v,
+            ray_length: ray.direction().norm()
         }
     }
 
+    pub fn with_tangents(mut self, dpdu: Vec3d, dpdv: Vec3d) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
     property! { front_face: bool }
+
+    // The normal on the front side of the surface, whichever side was hit.
+    #[inline]
+    pub fn outward_normal(&self) -> Vec3d {
+        if self.front_face { self.normal } else { -self.normal }
+    }
+
+    // Shading frame around the normal with u along dpdu, anisotropic materials align to it.
+    #[inline]
+    pub fn tangent_basis(&self) -> OrthonormalBasis {
+        OrthonormalBasis::from_w_u(self.normal, self.dpdu)
+    }
+
+    // Distance from the origin of the ray to the hit point.
+    #[inline]
+    pub fn distance(&self) -> f64 {
+        self.t * self.ray_length
+    }
 }
 
 pub trait Hittable {
@@ -40,17 +75,56 @@ pub trait Hittable {
         ray: &Ray,
         t_min: f64,
         t_max: f64
-    ) -> Option<HitRecord>;
+    ) -> Option<HitRecord<'_>>;
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB>;
+
+    // Emitters are sampled directly by the integrator, they have to implement
+    // `pdf_value` and `random` as well.
+    fn is_emitter(&self) -> bool {
+        false
+    }
+
+    // Solid angle density of `random` choosing `direction` seen from `origin`.
+    fn pdf_value(&self, _origin: Point3d, _direction: Vec3d) -> f64 {
+        0.0
+    }
+
+    // A random direction from `origin` towards this object.
+    fn random(&self, _origin: Point3d) -> Vec3d {
+        Vec3d::new(1.0, 0.0, 0.0)
+    }
 }
 
 impl Hittable for Box<dyn Hittable + Send + Sync> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         self.as_ref().hit(ray, t_min, t_max)
     }
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
         self.as_ref().bounding_box(time0, time1)
     }
+
+    fn is_emitter(&self) -> bool {
+        self.as_ref().is_emitter()
+    }
+
+    fn pdf_value(&self, origin: Point3d, direction: Vec3d) -> f64 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3d) -> Vec3d {
+        self.as_ref().random(origin)
+    }
+}
+
+// Density of sampling a point on a surface of `area` at the end of `direction`, in solid angle.
+pub fn area_pdf_to_solid_angle(hit: &HitRecord, direction: Vec3d, area: f64) -> f64 {
+    let distance_squared = hit.t * hit.t * direction.norm_squared();
+    let cosine = (direction.dot(&hit.normal) / direction.norm()).abs();
+    if cosine < 1e-8 {
+        0.0
+    } else {
+        distance_squared / (cosine * area)
+    }
 }
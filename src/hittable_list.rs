@@ -1,6 +1,7 @@
 use crate::hittable::{Hittable, HitRecord};
 use crate::ray::Ray;
 use crate::material::{Diffuse, Metal, Dielectric, DiffuseLight};
+use crate::principled::Principled;
 use crate::color::Color3d;
 use crate::sphere::{Sphere, MovingSphere};
 use crate::vec3::{Point3d, Vec3d};
@@ -83,7 +84,7 @@ impl HittableList {
                             )
                         ));
                     } else {
-                        let material = Box::new(Dielectric { index_refraction: 1.5 });
+                        let material = Box::new(Dielectric::new(1.5));
                         world.add(Box::new(
                             Sphere::new(
                                 center, 0.2, material
@@ -96,7 +97,7 @@ impl HittableList {
 
         world.add(Box::new(Sphere::new(
             Point3d::new(0.0, 1.0, 0.0), 1.0,
-            Box::new(Dielectric { index_refraction: 1.5 })
+            Box::new(Dielectric::new(1.5))
         )));
         world.add(Box::new(Sphere::new(
             Point3d::new(-4.0, 1.0, 0.0), 1.0,
@@ -127,6 +128,51 @@ impl HittableList {
         world
     }
 
+    // A row of spheres showing the layers of the principled material.
+    pub fn principled() -> Self {
+        let mut world = Self::new();
+        world.add(Box::new(Sphere::new(
+            Point3d::new(0.0, -1000.0, 0.0), 1000.0,
+            Diffuse::new(CheckerTexture::for_two_color(Color3d::new(0.2, 0.3, 0.1), Color3d::only(0.9)))
+        )));
+
+        let materials = vec![
+            // Plastic
+            Principled { roughness: Box::new(0.3), ..Principled::new(Color3d::new(0.8, 0.1, 0.1)) },
+            // Rough gold
+            Principled {
+                metallic: Box::new(1.0),
+                roughness: Box::new(0.35),
+                ..Principled::new(Color3d::new(1.0, 0.77, 0.34))
+            },
+            // Car paint
+            Principled {
+                roughness: Box::new(0.6),
+                clearcoat: Box::new(1.0),
+                clearcoat_roughness: Box::new(0.05),
+                ..Principled::new(Color3d::new(0.1, 0.2, 0.6))
+            },
+            // Velvet
+            Principled {
+                roughness: Box::new(1.0),
+                sheen: Box::new(1.0),
+                ..Principled::new(Color3d::new(0.5, 0.1, 0.4))
+            },
+            // Frosted glass
+            Principled {
+                transmission: Box::new(1.0),
+                roughness: Box::new(0.2),
+                ..Principled::new(1.0)
+            }
+        ];
+        for (i, material) in materials.into_iter().enumerate() {
+            let x = 2.1 * (i as f64 - 2.0);
+            world.add(Box::new(Sphere::new(Point3d::new(x, 1.0, 0.0), 1.0, material)));
+        }
+
+        world
+    }
+
     pub fn earth() -> Self {
         let mut world = Self::new();
         let earth_texture =
@@ -239,18 +285,18 @@ impl HittableList {
         let moving_sphere_material = Diffuse::for_color(Color3d::new(0.7, 0.3, 0.1));
         objects.add(Box::new(MovingSphere::new(center1, center2, 0.0, 1.0, 50.0, moving_sphere_material)));
         objects.add(Box::new(
-            Sphere::new(Point3d::new(260.0, 150.0, 45.0), 50.0, Dielectric { index_refraction: 1.5 })
+            Sphere::new(Point3d::new(260.0, 150.0, 45.0), 50.0, Dielectric::new(1.5))
         ));
         objects.add(Box::new(
             Sphere::new(Point3d::new(0.0, 150.0, 145.0), 50.0, Metal { albedo: Color3d::new(0.8, 0.8, 0.9), fuzz: 1.0 })
         ));
 
-        let boundary = Sphere::new(Point3d::new(360.0, 150.0, 145.0), 70.0, Dielectric { index_refraction: 1.5 });
+        let boundary = Sphere::new(Point3d::new(360.0, 150.0, 145.0), 70.0, Dielectric::new(1.5));
         objects.add(Box::new(boundary.clone()));
         objects.add(Box::new(ConstantMedium::for_color(boundary.clone(), 0.2, Color3d::new(0.2, 0.4, 0.9))));
         objects.add(Box::new(
             ConstantMedium::for_color(
-                Sphere::new(Point3d::zero(), 5000.0, Dielectric{index_refraction: 1.5}),
+                Sphere::new(Point3d::zero(), 5000.0, Dielectric::new(1.5)),
                 0.0001,
                 Color3d::one()
             )));
@@ -283,8 +329,8 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut hit: Option<HitRecord> = None;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut hit: Option<HitRecord<'_>> = None;
         let mut closest = t_max;
 
         for object in self.objects.iter() {
@@ -0,0 +1,222 @@
+use crate::color::Color3d;
+use crate::hittable::{Hittable, HitRecord};
+use crate::vec3::{Point3d, Vec3d};
+use crate::ray::Ray;
+use crate::light::Light;
+use crate::environment::Environment;
+use crate::pdf::{Pdf, HittablePdf, MixturePdf};
+use crate::sampler::{with_dimensions, CAMERA_DIMENSIONS, SCATTER_DIMENSIONS, LIGHT_DIMENSIONS, BOUNCE_DIMENSIONS};
+
+// Unidirectional path tracer. Emitters, a sampled environment and delta lights are sampled at
+// every non-specular hit, and emission reached by material sampling is weighted against light
+// sampling by MIS.
+pub struct PathTracer<'a, H: Hittable> {
+    pub world: &'a H,
+    pub emitters: Vec<&'a (dyn Hittable + Send + Sync)>,
+    pub lights: &'a [Box<dyn Light + Send + Sync>],
+    pub background: &'a (dyn Environment + Send + Sync),
+    pub max_depth: usize
+}
+
+// Directions towards one of the lights with an area, the environment counts as one light.
+enum LightPdf<'a> {
+    Emitter(HittablePdf<'a>),
+    Environment(&'a (dyn Environment + Send + Sync))
+}
+
+impl<'a> Pdf for LightPdf<'a> {
+    fn value(&self, direction: Vec3d) -> f64 {
+        match self {
+            LightPdf::Emitter(pdf) => pdf.value(direction),
+            LightPdf::Environment(environment) => environment.pdf_value(direction)
+        }
+    }
+
+    fn generate(&self) -> Vec3d {
+        match self {
+            LightPdf::Emitter(pdf) => pdf.generate(),
+            LightPdf::Environment(environment) => environment.random()
+        }
+    }
+}
+
+// Power heuristic of multiple importance sampling with two samples.
+#[inline]
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let pdf_squared = pdf * pdf;
+    pdf_squared / (pdf_squared + other_pdf * other_pdf)
+}
+
+impl<'a, H: Hittable> PathTracer<'a, H> {
+    pub fn ray_color(&self, ray: &Ray) -> Color3d {
+        self.trace(ray, self.max_depth, None)
+    }
+
+    // Directions from `origin` towards a uniformly chosen emitter or the environment.
+    fn light_pdf(&self, origin: Point3d) -> MixturePdf<LightPdf<'a>> {
+        let environment = Some(self.background).filter(|environment| environment.is_sampled());
+        MixturePdf::uniform(
+            self.emitters.iter()
+                .map(|&emitter| LightPdf::Emitter(HittablePdf::new(emitter, origin)))
+                .chain(environment.map(LightPdf::Environment))
+        )
+    }
+
+    // Radiance arriving along a shadow ray from the first thing it hits.
+    fn incident_radiance(&self, ray: &Ray) -> Color3d {
+        match self.world.hit(ray, 0.001, f64::INFINITY) {
+            Some(hit) => hit.material.emitted(&hit, -ray.direction().normalized()),
+            None => self.background.radiance(ray.direction())
+        }
+    }
+
+    // Emission found by material sampling, weighted against sampling the lights from the origin.
+    #[inline]
+    fn weight_emission(&self, emitted: Color3d, ray: &Ray, scattering_pdf: Option<f64>) -> Color3d {
+        match scattering_pdf {
+            Some(pdf) if !emitted.near_zero() =>
+                emitted * power_heuristic(pdf, self.light_pdf(ray.origin()).value(ray.direction())),
+            _ => emitted
+        }
+    }
+
+    // `scattering_pdf` is the density the ray was sampled with by the material of the previous hit,
+    // None for camera rays and specular bounces whose emission can not be reached by light sampling.
+    fn trace(&self, ray: &Ray, depth: usize, scattering_pdf: Option<f64>) -> Color3d {
+        if depth == 0 {
+            return Color3d::zero()
+        }
+
+        let hit = match self.world.hit(ray, 0.001, f64::INFINITY) {
+            Some(hit) => hit,
+            None => return self.weight_emission(self.background.radiance(ray.direction()), ray, scattering_pdf)
+        };
+
+        let wo = -ray.direction().normalized();
+        let emitted = self.weight_emission(hit.material.emitted(&hit, wo), ray, scattering_pdf);
+
+        let dimension = CAMERA_DIMENSIONS + (self.max_depth - depth) * BOUNCE_DIMENSIONS;
+        let sample = match with_dimensions(dimension, SCATTER_DIMENSIONS, || hit.material.sample(&hit, wo)) {
+            Some(sample) => sample,
+            None => return emitted
+        };
+
+        let scattered = Ray::new_with_time(hit.point, sample.direction, ray.time());
+        if sample.lobe.is_specular() {
+            return emitted + sample.weight * self.trace(&scattered, depth - 1, None)
+        }
+
+        let light_dimension = dimension + SCATTER_DIMENSIONS;
+        emitted
+            + with_dimensions(light_dimension, LIGHT_DIMENSIONS, || self.sample_light(ray, &hit, wo))
+            + self.sample_lights(ray, &hit, wo)
+            + sample.weight * self.trace(&scattered, depth - 1, Some(sample.pdf))
+    }
+
+    // Next event estimation: a shadow ray towards a random light, weighted against material sampling.
+    fn sample_light(&self, ray: &Ray, hit: &HitRecord, wo: Vec3d) -> Color3d {
+        let light_pdf = self.light_pdf(hit.point);
+        if light_pdf.is_empty() {
+            return Color3d::zero()
+        }
+
+        let direction = light_pdf.generate();
+        let light_pdf = light_pdf.value(direction);
+        if light_pdf <= 0.0 {
+            return Color3d::zero()
+        }
+
+        let wi = direction.normalized();
+        let scattering_pdf = hit.material.pdf(hit, wi, wo);
+        let bsdf = hit.material.eval(hit, wi, wo);
+        if scattering_pdf <= 0.0 || bsdf.near_zero() {
+            return Color3d::zero()
+        }
+
+        let shadow_ray = Ray::new_with_time(hit.point, direction, ray.time());
+        bsdf * self.incident_radiance(&shadow_ray) * (power_heuristic(light_pdf, scattering_pdf) / light_pdf)
+    }
+
+    // Delta lights can only be reached by shadow rays, every one of them is sampled.
+    fn sample_lights(&self, ray: &Ray, hit: &HitRecord, wo: Vec3d) -> Color3d {
+        self.lights.iter().filter_map(|light| {
+            let sample = light.sample(hit.point)?;
+            let bsdf = hit.material.eval(hit, sample.direction, wo);
+            if bsdf.near_zero() {
+                return None
+            }
+
+            let shadow_ray = Ray::new_with_time(hit.point, sample.direction, ray.time());
+            match self.world.hit(&shadow_ray, 0.001, sample.distance - 0.001) {
+                Some(_) => None,
+                None => Some(bsdf * sample.radiance)
+            }
+        }).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::acceleration::linear_bvh::LinearBVH;
+    use crate::camera::Camera;
+    use crate::environment::luminance;
+    use crate::scene_file::parse_scene;
+    use crate::util::{random_double, seed_random};
+
+    const CORNELL_BOX: &str = "
+        image width=16 spp=1 max_depth=5
+        camera look_from=278,278,-800 look_at=278,278,0 fov=40
+        material red diffuse albedo=0.65,0.05,0.05
+        material white diffuse albedo=0.73
+        material green diffuse albedo=0.12,0.45,0.15
+        material light diffuse_light emit=4
+        yz_rect from=0,0 to=555,555 k=555 material=green
+        yz_rect from=0,0 to=555,555 k=0 material=red
+        xz_rect from=0,0 to=555,555 k=0 material=white
+        xz_rect from=0,0 to=555,555 k=555 material=white
+        xy_rect from=0,0 to=555,555 k=555 material=white
+        xz_rect from=128,128 to=428,428 k=554 material=light
+        box min=130,0,65 max=295,165,230 material=white rotate_y=-18
+    ";
+
+    // Mean luminance over the image and its standard error.
+    fn estimate(tracer: &PathTracer<LinearBVH>, camera: &Camera, samples: u64) -> (f64, f64) {
+        let (mut sum, mut sum_squared) = (0.0, 0.0);
+        for index in 0..samples {
+            seed_random(7, index);
+            let ray = camera.get_ray(random_double(), random_double());
+            let value = luminance(tracer.ray_color(&ray));
+            sum += value;
+            sum_squared += value * value;
+        }
+        let mean = sum / samples as f64;
+        let variance = (sum_squared / samples as f64 - mean * mean).max(0.0);
+        (mean, (variance / samples as f64).sqrt())
+    }
+
+    #[test]
+    fn light_sampling_matches_material_sampling() {
+        let scene = parse_scene(CORNELL_BOX, Path::new(""), None).unwrap();
+        let bvh = LinearBVH::new(&scene.world.objects, 0.0, 1.0, scene.bvh_options);
+        let with_light_sampling = PathTracer {
+            world: &bvh,
+            emitters: scene.emitters(),
+            lights: &scene.lights,
+            background: scene.background.as_ref(),
+            max_depth: scene.max_depth
+        };
+        // Without emitters to sample, light is only found by material sampling with full weight.
+        let material_sampling_only = PathTracer { emitters: vec![], ..with_light_sampling };
+        assert!(!with_light_sampling.emitters.is_empty());
+
+        let (mis, mis_error) = estimate(&with_light_sampling, &scene.camera, 50000);
+        let (bsdf, bsdf_error) = estimate(&material_sampling_only, &scene.camera, 50000);
+        let error = (mis_error * mis_error + bsdf_error * bsdf_error).sqrt();
+        assert!(mis > 0.0 && bsdf > 0.0);
+        assert!((mis - bsdf).abs() < 4.0 * error, "{} ± {} against {} ± {}", mis, mis_error, bsdf, bsdf_error);
+        assert!(mis_error < bsdf_error);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::hittable::HitRecord;
+use crate::color::Color3d;
+use crate::vec3::{Point3d, Vec3d};
+use crate::util::{random_double, random_2d};
+use crate::texture::Texture;
+use crate::material::{Material, ScatterSample, Lobe};
+use crate::microfacet::{TrowbridgeReitz, fresnel_dielectric, reflect};
+
+// Blends two materials, `mask` is the weight of `b` and is read from the mean of the channels of
+// the texture. Scattering picks one of the materials at random.
+pub struct MixMaterial<A: Material, B: Material> {
+    pub a: A,
+    pub b: B,
+    pub mask: Box<dyn Texture>
+}
+
+impl<A: Material, B: Material> MixMaterial<A, B> {
+    pub fn new<T: Texture + 'static>(a: A, b: B, mask: T) -> Self {
+        Self { a, b, mask: Box::new(mask) }
+    }
+
+    fn weight(&self, u: f64, v: f64, p: Point3d) -> f64 {
+        let mask = self.mask.eval(u, v, p);
+        ((mask.x + mask.y + mask.z) / 3.0).clamp(0.0, 1.0)
+    }
+}
+
+impl<A: Material, B: Material> Material for MixMaterial<A, B> {
+    fn eval(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> Color3d {
+        let t = self.weight(hit_record.u, hit_record.v, hit_record.point);
+        self.a.eval(hit_record, wi, wo) * (1.0 - t) + self.b.eval(hit_record, wi, wo) * t
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> f64 {
+        let t = self.weight(hit_record.u, hit_record.v, hit_record.point);
+        self.a.pdf(hit_record, wi, wo) * (1.0 - t) + self.b.pdf(hit_record, wi, wo) * t
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+        let t = self.weight(hit_record.u, hit_record.v, hit_record.point);
+        let (sample, probability) = if random_double() < t {
+            (self.b.sample(hit_record, wo)?, t)
+        } else {
+            (self.a.sample(hit_record, wo)?, 1.0 - t)
+        };
+
+        // Specular directions can only come from the chosen material.
+        if sample.lobe.is_specular() {
+            return Some(ScatterSample {
+                pdf: sample.pdf * probability,
+                ..sample
+            })
+        }
+
+        let pdf = self.pdf(hit_record, sample.direction, wo);
+        if pdf <= 0.0 {
+            return None
+        }
+        Some(ScatterSample {
+            weight: self.eval(hit_record, sample.direction, wo) / pdf,
+            pdf,
+            ..sample
+        })
+    }
+
+    fn emitted(&self, hit_record: &HitRecord, wo: Vec3d) -> Color3d {
+        let t = self.weight(hit_record.u, hit_record.v, hit_record.point);
+        self.a.emitted(hit_record, wo) * (1.0 - t) + self.b.emitted(hit_record, wo) * t
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.a.is_emissive() || self.b.is_emissive()
+    }
+}
+
+// A thin dielectric layer over any material, like varnish on wood. The coat reflects light by
+// the Fresnel equations, the base receives what is transmitted into the coat and back out.
+// Refraction of directions inside the layer and reflections between the layers are ignored.
+// Surfaces hit from the inside are the bare base.
+pub struct Coated<M: Material> {
+    pub base: M,
+    pub index_refraction: f64,
+    pub distribution: TrowbridgeReitz
+}
+
+impl<M: Material> Coated<M> {
+    pub fn new(base: M, index_refraction: f64, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            base,
+            index_refraction,
+            distribution: TrowbridgeReitz::new(
+                TrowbridgeReitz::roughness_to_alpha(roughness_u),
+                TrowbridgeReitz::roughness_to_alpha(roughness_v)
+            )
+        }
+    }
+
+    #[inline]
+    fn fresnel(&self, cos_theta: f64) -> f64 {
+        fresnel_dielectric(cos_theta, self.index_refraction)
+    }
+
+    // Reflection of the rough coat times the cosine and its density, in the local frame.
+    fn eval_pdf_coat(&self, wi: Vec3d, wo: Vec3d) -> (f64, f64) {
+        if self.distribution.is_smooth() || wi.z <= 0.0 || wo.z <= 0.0 {
+            return (0.0, 0.0)
+        }
+        let wm = (wi + wo).normalized();
+        let f = self.fresnel(wo.dot(&wm)) * self.distribution.d(wm) * self.distribution.g(wo, wi) / (4.0 * wo.z);
+        (f, self.distribution.reflection_pdf(wo, wm))
+    }
+
+    // The coat is sampled with the probability of reflection at normal `wo`.
+    fn eval_pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> (Color3d, f64) {
+        let basis = hit_record.tangent_basis();
+        let (wi_local, wo_local) = (basis.world_to_local(wi), basis.world_to_local(wo));
+        let coat_probability = self.fresnel(wo_local.z);
+        let (coat, coat_pdf) = self.eval_pdf_coat(wi_local, wo_local);
+
+        let transmitted = (1.0 - self.fresnel(wi_local.z.abs())) * (1.0 - coat_probability);
+        let f = self.base.eval(hit_record, wi, wo) * transmitted + Color3d::only(coat);
+        let pdf = coat_probability * coat_pdf + (1.0 - coat_probability) * self.base.pdf(hit_record, wi, wo);
+        (f, pdf)
+    }
+}
+
+impl<M: Material> Material for Coated<M> {
+    fn eval(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> Color3d {
+        if !hit_record.front_face() {
+            return self.base.eval(hit_record, wi, wo)
+        }
+        self.eval_pdf(hit_record, wi, wo).0
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> f64 {
+        if !hit_record.front_face() {
+            return self.base.pdf(hit_record, wi, wo)
+        }
+        self.eval_pdf(hit_record, wi, wo).1
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+        if !hit_record.front_face() {
+            return self.base.sample(hit_record, wo)
+        }
+
+        let basis = hit_record.tangent_basis();
+        let wo_local = basis.world_to_local(wo);
+        let coat_probability = self.fresnel(wo_local.z);
+
+        let (direction, lobe) = if random_double() < coat_probability {
+            if self.distribution.is_smooth() {
+                // The Fresnel reflectance cancels with the probability of choosing the coat.
+                return Some(ScatterSample {
+                    direction: reflect(wo, hit_record.normal),
+                    weight: Color3d::one(),
+                    pdf: coat_probability,
+                    lobe: Lobe::Specular
+                })
+            }
+            let wm = self.distribution.sample_visible(wo_local, random_2d());
+            (basis.local(reflect(wo_local, wm)), Lobe::Glossy)
+        } else {
+            let sample = self.base.sample(hit_record, wo)?;
+            if sample.lobe.is_specular() {
+                let cos_theta_i = hit_record.normal.dot(&sample.direction).abs();
+                return Some(ScatterSample {
+                    weight: sample.weight * (1.0 - self.fresnel(cos_theta_i)),
+                    pdf: sample.pdf * (1.0 - coat_probability),
+                    ..sample
+                })
+            }
+            (sample.direction, sample.lobe)
+        };
+
+        let (f, pdf) = self.eval_pdf(hit_record, direction, wo);
+        if pdf <= 0.0 {
+            return None
+        }
+        Some(ScatterSample {
+            direction,
+            weight: f / pdf,
+            pdf,
+            lobe
+        })
+    }
+
+    fn emitted(&self, hit_record: &HitRecord, wo: Vec3d) -> Color3d {
+        self.base.emitted(hit_record, wo)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+}
@@ -0,0 +1,124 @@
+use crate::vec3::{Point3d, Vec3d};
+use crate::color::Color3d;
+use crate::util::Angle;
+
+// Light arriving at a point from a delta light.
+#[derive(Copy, Clone, Debug)]
+pub struct LightSample {
+    // Unit direction from the shaded point towards the light.
+    pub direction: Vec3d,
+    // Infinite for lights without a position.
+    pub distance: f64,
+    pub radiance: Color3d
+}
+
+// Lights with no area, they can not be hit by rays and are only reached by shadow rays.
+pub trait Light {
+    fn sample(&self, point: Point3d) -> Option<LightSample>;
+}
+
+pub struct PointLight {
+    pub position: Point3d,
+    pub intensity: Color3d
+}
+
+impl PointLight {
+    pub fn new(position: Point3d, intensity: Color3d) -> Self {
+        Self { position, intensity }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: Point3d) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance_squared = to_light.norm_squared();
+        let distance = distance_squared.sqrt();
+
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: self.intensity / distance_squared
+        })
+    }
+}
+
+// A point light shining into a cone. The intensity falls off smoothly between the falloff
+// angle and the cone angle, both measured from the axis.
+pub struct SpotLight {
+    pub position: Point3d,
+    direction: Vec3d,
+    pub intensity: Color3d,
+    cos_total_width: f64,
+    cos_falloff_start: f64
+}
+
+impl SpotLight {
+    pub fn new(position: Point3d, look_at: Point3d, intensity: Color3d, cone_angle: Angle, falloff_angle: Angle) -> Self {
+        let cos_total_width = cone_angle.rad().cos();
+        Self {
+            position,
+            direction: (look_at - position).normalized(),
+            intensity,
+            cos_total_width,
+            cos_falloff_start: falloff_angle.rad().cos().max(cos_total_width)
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta <= self.cos_total_width {
+            0.0
+        } else if cos_theta >= self.cos_falloff_start {
+            1.0
+        } else {
+            let t = (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: Point3d) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance_squared = to_light.norm_squared();
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+
+        let falloff = self.falloff(-direction.dot(&self.direction));
+        if falloff <= 0.0 {
+            return None
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.intensity * (falloff / distance_squared)
+        })
+    }
+}
+
+// Parallel light from infinitely far away like the sun, `irradiance` is measured on a surface
+// facing the light.
+pub struct DirectionalLight {
+    direction: Vec3d,
+    pub irradiance: Color3d
+}
+
+impl DirectionalLight {
+    // `direction` is the direction the light travels in.
+    pub fn new(direction: Vec3d, irradiance: Color3d) -> Self {
+        Self {
+            direction: direction.normalized(),
+            irradiance
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: Point3d) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction,
+            distance: f64::INFINITY,
+            radiance: self.irradiance
+        })
+    }
+}
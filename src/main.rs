@@ -1,23 +1,32 @@
-use crate::scene::Scene;
-use crate::hittable_list::HittableList;
-use crate::vec3::{Point3d, Vec3d};
-use crate::camera::Camera;
-use crate::util::Angle;
-use crate::color::Color3d;
+use std::error::Error;
+use crate::scene::{Scene, MAX_IMAGE_SIZE, MAX_IMAGE_PIXELS};
+use crate::cli::{Command, RenderOptions, OutputFormat};
 
 #[macro_use]
 mod util;
+mod sampler;
 // Macro modules must appear before modules using its macros.
 mod vec3;
 mod ppm;
+mod exr;
 mod color;
+mod tone_mapping;
 mod ray;
 mod hittable;
 mod sphere;
 mod hittable_list;
 mod camera;
 mod material;
+mod microfacet;
+mod principled;
+mod layered;
+mod bump;
 mod vec3d_extensions;
+mod pdf;
+mod light;
+mod integrator;
+mod distribution;
+mod environment;
 mod scene;
 mod acceleration;
 mod texture;
@@ -26,53 +35,112 @@ mod image_texture;
 mod rectangle;
 mod transformations;
 mod subsurface;
+mod triangle;
+mod wavefront;
+mod scene_file;
+mod presets;
+mod cli;
 
-fn get_scene() -> Scene {
-    // let aspect_ratio = 16.0 / 9.0;
-    let aspect_ratio = 1.0;
-    let image_width = 800;
-    let image_height = (image_width as f64 / aspect_ratio) as usize;
+fn load_scene(options: &RenderOptions) -> Result<Scene, Box<dyn Error>> {
+    // Random scenes and noise textures are built from the same seed as the image.
+    let mut scene = if options.is_builtin_scene() {
+        util::seed_scene_random(options.seed.unwrap_or(0));
+        presets::builtin_scene(&options.scene).unwrap()
+    } else {
+        scene_file::load_scene(&options.scene, options.seed)
+            .map_err(|e| format!("{}: {}", options.scene, e))?
+    };
 
-    let samples_per_pixel = 10000;
+    let (width, height) = match (options.width, options.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, (width * scene.height / scene.width).max(2)),
+        (None, Some(height)) => ((height * scene.width / scene.height).max(2), height),
+        (None, None) => (scene.width, scene.height)
+    };
+    if width > MAX_IMAGE_SIZE || height > MAX_IMAGE_SIZE {
+        return Err(format!("the image can be at most {} pixels wide and high", MAX_IMAGE_SIZE).into());
+    }
+    if width * height > MAX_IMAGE_PIXELS {
+        return Err(format!("the image can have at most {} pixels, {}x{} has {}",
+                           MAX_IMAGE_PIXELS, width, height, width * height).into());
+    }
+    if (width, height) != (scene.width, scene.height) {
+        scene.set_resolution(width, height);
+    }
+    if let Some(spp) = options.spp {
+        scene.spp = spp;
+    }
+    if let Some(max_depth) = options.max_depth {
+        scene.max_depth = max_depth;
+    }
+    if let Some(background) = options.background {
+        scene.background = Box::new(background);
+    }
+    if let Some(exposure) = options.exposure {
+        scene.tone_mapping.exposure = exposure;
+    }
+    if let Some(operator) = options.tone_operator {
+        // Picking the operator of the scene again keeps its white point.
+        let white = scene.tone_mapping.operator.white();
+        scene.tone_mapping.operator = white.and_then(|white| operator.with_white(white)).unwrap_or(operator);
+    }
+    if let Some(white) = options.white {
+        scene.tone_mapping.operator = scene.tone_mapping.operator.with_white(white)
+            .ok_or("`--white` only applies to the extended_reinhard tone mapping")?;
+    }
+    scene.bvh_options = options.bvh_options;
+    if let Some(seed) = options.seed {
+        scene.seed = seed;
+    }
+    if let Some(sampler) = options.sampler {
+        scene.sampler = sampler;
+    }
+    if let Some(tile_size) = options.tile_size {
+        scene.tile_size = tile_size;
+    }
+    scene.verbose = options.verbose;
 
-    // let world = HittableList::random();
-    // let world = HittableList::perlin_noise();
-    // let world = HittableList::earth();
-    let world = HittableList::all_feature_box();
-
-    let aperture = 0.0;
-    let dist_to_focus = 10.0;
-    let look_from = Point3d::new(478.0, 278.0, -600.0);
-    let look_at = Point3d::new(278.0, 278.0, 0.0);
-    // let look_at = Point3d::zero();
-    let camera = Camera::new_with_shutter(
-        look_from,
-        look_at,
-        Vec3d::new(0.0, 1.0, 0.0),
-        aspect_ratio,
-        Angle::DegAngle(40.0),
-        aperture,
-        dist_to_focus,
-        0.0, 1.0
-    );
+    Ok(scene)
+}
 
-    // let background = Color3d::new(0.70, 0.80, 1.00);
-    let background = Color3d::zero();
+fn render(options: RenderOptions) -> Result<(), Box<dyn Error>> {
+    let scene = load_scene(&options)?;
+    let file = if options.serial {
+        scene.get_ppm_file()
+    } else {
+        scene.get_ppm_file_parallel()
+    };
 
-    let scene = Scene::new(
-        image_height,
-        image_width,
-        world,
-        camera,
-        samples_per_pixel,
-        background,
-    );
+    match options.format {
+        OutputFormat::Ppm => file.write_to(options.output)?,
+        OutputFormat::Jpeg => file.write_jpeg(options.output, options.quality)?,
+        OutputFormat::Hdr => file.write_hdr(options.output)?,
+        OutputFormat::Exr => file.write_exr(options.output)?,
+        OutputFormat::Image(format) => file.image_buffer().save_with_format(&options.output, format)?
+    }
 
-    scene
+    Ok(())
 }
 
 fn main() {
-    let path = "image.ppm";
+    let result = match Command::parse(std::env::args().skip(1)) {
+        Ok(Command::Render(options)) => render(*options),
+        Ok(Command::ListScenes) => {
+            presets::SCENE_NAMES.iter().for_each(|name| println!("{}", name));
+            Ok(())
+        }
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    get_scene().get_ppm_file_parallel().write_to(path.to_string()).unwrap()
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
 }
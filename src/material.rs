@@ -1,41 +1,99 @@
-use crate::ray::Ray;
 use crate::hittable::HitRecord;
 use crate::color::Color3d;
-use crate::vec3::{Point3d, Vec3d};
+use crate::vec3::Vec3d;
 use std::ops::{Neg, Deref};
-use crate::util::random_double;
+use crate::util::{random_double, random_2d};
 use crate::texture::{Texture, SolidColor};
+use crate::vec3d_extensions::{OrthonormalBasis, cosine_hemisphere_pdf, uniform_sphere_pdf};
+use crate::microfacet::{TrowbridgeReitz, fresnel_conductor, fresnel_dielectric, reflect, refract};
+use std::f64::consts::PI;
+
+// Whether a scattered direction was picked from a continuous distribution that `eval` and `pdf`
+// describe, or from a delta distribution that can only be sampled.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    Glossy,
+    Specular
+}
 
-pub trait Material {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Color3d, Ray)>;
+impl Lobe {
+    #[inline]
+    pub fn is_specular(self) -> bool {
+        self == Lobe::Specular
+    }
+}
 
-    fn emitted(&self, u: f64, v: f64, p: Point3d) -> Color3d;
+#[derive(Copy, Clone, Debug)]
+pub struct ScatterSample {
+    // Unit direction away from the surface.
+    pub direction: Vec3d,
+    // `eval` divided by `pdf` for non-specular lobes, the whole throughput for specular ones.
+    pub weight: Color3d,
+    // Solid angle density, or the probability of choosing the lobe for specular samples.
+    pub pdf: f64,
+    pub lobe: Lobe
 }
 
-impl Material for Box<dyn Material + Send + Sync> {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Color3d, Ray)> {
-        self.as_ref().scatter(ray_in, hit_record)
+// Directions are unit vectors pointing away from the hit point, `wo` towards the viewer and
+// `wi` towards the light.
+pub trait Material {
+    // BSDF times the cosine between `wi` and the normal, zero for specular materials.
+    fn eval(&self, _hit_record: &HitRecord, _wi: Vec3d, _wo: Vec3d) -> Color3d {
+        Color3d::zero()
     }
 
-    fn emitted(&self, u: f64, v: f64, p: Point3d) -> Color3d {
-        self.as_ref().emitted(u, v, p)
+    // Solid angle density of `sample` choosing `wi`, zero for specular materials.
+    fn pdf(&self, _hit_record: &HitRecord, _wi: Vec3d, _wo: Vec3d) -> f64 {
+        0.0
     }
-}
 
-impl<M: Material + Send + Sync> Material for Box<M> {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Color3d, Ray)> {
-        self.as_ref().scatter(ray_in, hit_record)
-    }
+    fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample>;
+
+    // Radiance emitted from the hit point towards `wo`.
+    fn emitted(&self, hit_record: &HitRecord, wo: Vec3d) -> Color3d;
 
-    fn emitted(&self, u: f64, v: f64, p: Point3d) -> Color3d {
-        self.as_ref().emitted(u, v, p)
+    fn is_emissive(&self) -> bool {
+        false
     }
 }
 
+macro_rules! forward_material {
+    () => {
+        fn eval(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> Color3d {
+            self.as_ref().eval(hit_record, wi, wo)
+        }
+
+        fn pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> f64 {
+            self.as_ref().pdf(hit_record, wi, wo)
+        }
+
+        fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+            self.as_ref().sample(hit_record, wo)
+        }
+
+        fn emitted(&self, hit_record: &HitRecord, wo: Vec3d) -> Color3d {
+            self.as_ref().emitted(hit_record, wo)
+        }
+
+        fn is_emissive(&self) -> bool {
+            self.as_ref().is_emissive()
+        }
+    };
+}
+
+impl Material for Box<dyn Material + Send + Sync> {
+    forward_material!();
+}
+
+impl<M: Material + Send + Sync> Material for Box<M> {
+    forward_material!();
+}
+
 macro_rules! no_emission {
     () => {
         #[inline]
-        fn emitted(&self, u: f64, v: f64, p: Point3d) -> Color3d {
+        fn emitted(&self, _hit_record: &HitRecord, _wo: Vec3d) -> Color3d {
             Color3d::zero()
         }
     };
@@ -61,15 +119,29 @@ impl Diffuse<SolidColor> {
 }
 
 impl<T: Texture> Material for Diffuse<T> {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Color3d, Ray)> {
-        let scatter_direction =
-            match hit_record.normal + Vec3d::random_in_unit_sphere().normalized() {
-                m if m.near_zero() => hit_record.normal,
-                m => m
-            };
+    fn eval(&self, hit_record: &HitRecord, wi: Vec3d, _wo: Vec3d) -> Color3d {
+        let cosine = hit_record.normal.dot(&wi);
+        if cosine > 0.0 {
+            self.albedo.eval(hit_record.u, hit_record.v, hit_record.point) * (cosine / PI)
+        } else {
+            Color3d::zero()
+        }
+    }
 
-        let color = self.albedo.eval(hit_record.u, hit_record.v, hit_record.point);
-        Some((color, Ray::new_with_time(hit_record.point, scatter_direction, ray_in.time())))
+    fn pdf(&self, hit_record: &HitRecord, wi: Vec3d, _wo: Vec3d) -> f64 {
+        cosine_hemisphere_pdf(hit_record.normal.dot(&wi))
+    }
+
+    fn sample(&self, hit_record: &HitRecord, _wo: Vec3d) -> Option<ScatterSample> {
+        let local = Vec3d::random_cosine_direction();
+        let direction = OrthonormalBasis::from_w(hit_record.normal).local(local);
+
+        Some(ScatterSample {
+            direction,
+            weight: self.albedo.eval(hit_record.u, hit_record.v, hit_record.point),
+            pdf: cosine_hemisphere_pdf(local.z),
+            lobe: Lobe::Diffuse
+        })
     }
 
     no_emission!();
@@ -82,12 +154,18 @@ pub struct Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Color3d, Ray)> {
-        let reflected = ray_in.direction().normalized().reflect(&hit_record.normal);
+    // The fuzzy reflection has no closed form density, so it is handled like a specular lobe.
+    fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+        let reflected = wo.neg().reflect(&hit_record.normal);
         let fuzzed = reflected + self.fuzz * Vec3d::random_in_unit_sphere();
 
         if fuzzed.dot(&hit_record.normal) > 0.0 {
-            Some((self.albedo, Ray::new_with_time(hit_record.point, fuzzed, ray_in.time())))
+            Some(ScatterSample {
+                direction: fuzzed.normalized(),
+                weight: self.albedo,
+                pdf: 1.0,
+                lobe: Lobe::Specular
+            })
         } else {
             None
         }
@@ -96,63 +174,400 @@ impl Material for Metal {
     no_emission!();
 }
 
+// Complex indices of refraction (eta, k) of metals at red, green and blue wavelengths.
+pub const CONDUCTOR_PRESETS: [(&str, [f64; 3], [f64; 3]); 4] = [
+    ("gold", [0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+    ("copper", [0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+    ("aluminium", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+    ("silver", [0.155, 0.117, 0.138], [4.828, 3.122, 2.147])
+];
+
+// A rough metal with GGX microfacets. Anisotropic roughness is along dpdu and dpdv of the surface.
+#[derive(Clone)]
+pub struct Conductor {
+    pub eta: Color3d,
+    pub k: Color3d,
+    pub distribution: TrowbridgeReitz
+}
+
+impl Conductor {
+    // Equal roughness along both tangents makes the surface isotropic.
+    pub fn new(eta: Color3d, k: Color3d, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            eta, k,
+            distribution: TrowbridgeReitz::new(
+                TrowbridgeReitz::roughness_to_alpha(roughness_u),
+                TrowbridgeReitz::roughness_to_alpha(roughness_v)
+            )
+        }
+    }
+
+    // One of `CONDUCTOR_PRESETS` by name.
+    pub fn preset(name: &str, roughness_u: f64, roughness_v: f64) -> Option<Self> {
+        CONDUCTOR_PRESETS.iter().find(|(preset, _, _)| *preset == name).map(|&(_, [er, eg, eb], [kr, kg, kb])| {
+            Self::new(Color3d::new(er, eg, eb), Color3d::new(kr, kg, kb), roughness_u, roughness_v)
+        })
+    }
+}
+
+impl Material for Conductor {
+    fn eval(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> Color3d {
+        if self.distribution.is_smooth() {
+            return Color3d::zero()
+        }
+
+        let basis = hit_record.tangent_basis();
+        let (wi, wo) = (basis.world_to_local(wi), basis.world_to_local(wo));
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return Color3d::zero()
+        }
+
+        let wm = (wi + wo).normalized();
+        let fresnel = fresnel_conductor(wo.dot(&wm), self.eta, self.k);
+        // D F G / (4 cos θo cos θi), times cos θi.
+        fresnel * (self.distribution.d(wm) * self.distribution.g(wo, wi) / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0
+        }
+
+        let basis = hit_record.tangent_basis();
+        let (wi, wo) = (basis.world_to_local(wi), basis.world_to_local(wo));
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return 0.0
+        }
+
+        self.distribution.reflection_pdf(wo, (wi + wo).normalized())
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+        let basis = hit_record.tangent_basis();
+        let wo_local = basis.world_to_local(wo);
+        if wo_local.z <= 0.0 {
+            return None
+        }
+
+        if self.distribution.is_smooth() {
+            return Some(ScatterSample {
+                direction: reflect(wo, hit_record.normal),
+                weight: fresnel_conductor(wo_local.z, self.eta, self.k),
+                pdf: 1.0,
+                lobe: Lobe::Specular
+            })
+        }
+
+        let wm = self.distribution.sample_visible(wo_local, random_2d());
+        let wi = reflect(wo_local, wm);
+        if wi.z <= 0.0 {
+            return None
+        }
+
+        let pdf = self.distribution.reflection_pdf(wo_local, wm);
+        let direction = basis.local(wi);
+        Some(ScatterSample {
+            direction,
+            weight: self.eval(hit_record, direction, wo) / pdf,
+            pdf,
+            lobe: Lobe::Glossy
+        })
+    }
+
+    no_emission!();
+}
+
+// Light travelling through a medium with the absorption coefficient `absorption` is attenuated
+// following the Beer-Lambert law. A ray leaving a closed object has been inside since its last hit.
+fn transmittance(absorption: Color3d, hit_record: &HitRecord) -> Color3d {
+    if hit_record.front_face() || absorption.near_zero() {
+        return Color3d::one()
+    }
+
+    let distance = hit_record.distance();
+    Color3d::new(
+        (-absorption.x * distance).exp(),
+        (-absorption.y * distance).exp(),
+        (-absorption.z * distance).exp()
+    )
+}
+
+// Reflects with the Fresnel reflectance of a smooth interface and refracts otherwise. `eta` is the
+// index of refraction behind the surface over the one on the side of the normal.
+fn sample_smooth_dielectric(hit_record: &HitRecord, wo: Vec3d, eta: f64, absorption: Color3d) -> Option<ScatterSample> {
+    let normal = hit_record.normal;
+    let reflectance = fresnel_dielectric(wo.dot(&normal), eta);
+    let (direction, pdf) = if random_double() < reflectance {
+        (reflect(wo, normal), reflectance)
+    } else {
+        (refract(wo, normal, eta)?, 1.0 - reflectance)
+    };
+
+    Some(ScatterSample {
+        direction,
+        weight: transmittance(absorption, hit_record),
+        pdf,
+        lobe: Lobe::Specular
+    })
+}
+
 #[derive(Clone)]
 pub struct Dielectric {
-    pub index_refraction: f64
+    pub index_refraction: f64,
+    // Per unit length, clear glass does not absorb.
+    pub absorption: Color3d
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Color3d, Ray)> {
-        let refraction_ratio =
-            if hit_record.front_face() {
-                1.0 / self.index_refraction
-            } else {
-                self.index_refraction
-            };
-        let unit_redirection = ray_in.direction().normalized();
-        let cos_theta = unit_redirection.neg().dot(&hit_record.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-
-        let full_reflect = refraction_ratio * sin_theta > 1.0;
-        let direction =
-            if full_reflect || Self::reflectance(cos_theta, refraction_ratio) > random_double() {
-                unit_redirection.reflect(&hit_record.normal)
-            } else {
-                unit_redirection.refract(&hit_record.normal, refraction_ratio)
-            };
-        Some((Color3d::one(), Ray::new_with_time(hit_record.point, direction, ray_in.time())))
+    fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+        let eta = if hit_record.front_face() { self.index_refraction } else { 1.0 / self.index_refraction };
+        sample_smooth_dielectric(hit_record, wo, eta, self.absorption)
     }
 
     no_emission!();
 }
 
 impl Dielectric {
-    fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-        // Use Schlick's approximation for reflectance.
-        let r = (1.0 - ref_idx) / (1.0 + ref_idx);
-        let r0 = r * r;
+    pub fn new(index_refraction: f64) -> Self {
+        Self { index_refraction, absorption: Color3d::zero() }
+    }
+
+    pub fn with_absorption(mut self, absorption: Color3d) -> Self {
+        self.absorption = absorption;
+        self
+    }
+}
+
+// Glass with GGX microfacets reflecting and refracting light, after Walter et al. "Microfacet
+// Models for Refraction through Rough Surfaces". Below a tiny roughness it is the smooth interface
+// of `Dielectric`.
+#[derive(Clone)]
+pub struct RoughDielectric {
+    pub index_refraction: f64,
+    pub absorption: Color3d,
+    pub distribution: TrowbridgeReitz
+}
 
-        r0 + (1.0 - r0) * f64::powf(1.0 - cosine, 5.0)
+impl RoughDielectric {
+    pub fn new(index_refraction: f64, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            index_refraction,
+            absorption: Color3d::zero(),
+            distribution: TrowbridgeReitz::new(
+                TrowbridgeReitz::roughness_to_alpha(roughness_u),
+                TrowbridgeReitz::roughness_to_alpha(roughness_v)
+            )
+        }
+    }
+
+    pub fn with_absorption(mut self, absorption: Color3d) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    // Matching indices of refraction let light pass straight through, whatever the roughness.
+    #[inline]
+    fn is_specular(&self) -> bool {
+        self.index_refraction == 1.0 || self.distribution.is_smooth()
+    }
+
+    // Index of refraction behind the surface over the one on the side of the normal.
+    #[inline]
+    fn eta(&self, hit_record: &HitRecord) -> f64 {
+        if hit_record.front_face() { self.index_refraction } else { 1.0 / self.index_refraction }
+    }
+
+    // The microfacet normal that scatters `wo` into `wi` in the local frame, where `wo` is above
+    // the surface. None for directions no visible microfacet connects.
+    fn half_vector(&self, wi: Vec3d, wo: Vec3d, eta: f64) -> Option<Vec3d> {
+        if wi.z == 0.0 || wo.z <= 0.0 {
+            return None
+        }
+
+        let etap = if wi.z > 0.0 { 1.0 } else { eta };
+        let wm = wi * etap + wo;
+        if wm.near_zero() {
+            return None
+        }
+        let wm = wm.normalized();
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+
+        // Microfacets facing away from either direction are not visible.
+        if wm.dot(&wi) * wi.z < 0.0 || wm.dot(&wo) * wo.z < 0.0 {
+            None
+        } else {
+            Some(wm)
+        }
+    }
+
+    // BSDF times the cosine and the density of sampling `wi`, in the local frame.
+    fn eval_pdf(&self, wi: Vec3d, wo: Vec3d, eta: f64) -> (f64, f64) {
+        let wm = match self.half_vector(wi, wo, eta) {
+            Some(wm) => wm,
+            None => return (0.0, 0.0)
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        let d = self.distribution.d(wm);
+        let g = self.distribution.g(wo, wi);
+        if wi.z > 0.0 {
+            let f = d * g * reflectance / (4.0 * wo.z);
+            (f, self.distribution.reflection_pdf(wo, wm) * reflectance)
+        } else {
+            // Change of variables from the microfacet normal to the refracted direction.
+            let denominator = wi.dot(&wm) + wo.dot(&wm) / eta;
+            let denominator = denominator * denominator;
+            let f = d * (1.0 - reflectance) * g * (wi.dot(&wm) * wo.dot(&wm) / (denominator * wo.z)).abs();
+            let dwm_dwi = wi.dot(&wm).abs() / denominator;
+            (f, self.distribution.visible_d(wo, wm) * dwm_dwi * (1.0 - reflectance))
+        }
+    }
+
+}
+
+impl Material for RoughDielectric {
+    fn eval(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> Color3d {
+        if self.is_specular() {
+            return Color3d::zero()
+        }
+
+        let basis = hit_record.tangent_basis();
+        let (f, _) = self.eval_pdf(basis.world_to_local(wi), basis.world_to_local(wo), self.eta(hit_record));
+        transmittance(self.absorption, hit_record) * f
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> f64 {
+        if self.is_specular() {
+            return 0.0
+        }
+
+        let basis = hit_record.tangent_basis();
+        let (_, pdf) = self.eval_pdf(basis.world_to_local(wi), basis.world_to_local(wo), self.eta(hit_record));
+        pdf
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+        let eta = self.eta(hit_record);
+        if self.is_specular() {
+            return sample_smooth_dielectric(hit_record, wo, eta, self.absorption)
+        }
+
+        let basis = hit_record.tangent_basis();
+        let wo_local = basis.world_to_local(wo);
+        if wo_local.z <= 0.0 {
+            return None
+        }
+
+        let wm = self.distribution.sample_visible(wo_local, random_2d());
+        let reflectance = fresnel_dielectric(wo_local.dot(&wm), eta);
+        let wi = if random_double() < reflectance {
+            let wi = reflect(wo_local, wm);
+            if wi.z <= 0.0 {
+                return None
+            }
+            wi
+        } else {
+            let wi = refract(wo_local, wm, eta)?;
+            if wi.z >= 0.0 {
+                return None
+            }
+            wi
+        };
+
+        let (f, pdf) = self.eval_pdf(wi, wo_local, eta);
+        if pdf <= 0.0 {
+            return None
+        }
+
+        Some(ScatterSample {
+            direction: basis.local(wi),
+            weight: transmittance(self.absorption, hit_record) * (f / pdf),
+            pdf,
+            lobe: Lobe::Glossy
+        })
+    }
+
+    no_emission!();
+}
+
+// Emission of a texture, from both faces of the surface or only from the front face the normal
+// points out of.
+#[inline]
+fn emission<T: Texture>(emit: &T, two_sided: bool, hit_record: &HitRecord) -> Color3d {
+    if two_sided || hit_record.front_face() {
+        emit.eval(hit_record.u, hit_record.v, hit_record.point)
+    } else {
+        Color3d::zero()
     }
 }
 
 pub struct DiffuseLight<T: Texture> {
-    emit: T
+    emit: T,
+    two_sided: bool
 }
 
 impl<T: Texture> DiffuseLight<T> {
     pub fn new(emit: T) -> Self {
-        Self { emit }
+        Self { emit, two_sided: true }
+    }
+
+    pub fn one_sided(mut self) -> Self {
+        self.two_sided = false;
+        self
     }
 }
 
 impl<T: Texture> Material for DiffuseLight<T> {
-    fn scatter(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> Option<(Color3d, Ray)> {
+    fn sample(&self, _hit_record: &HitRecord, _wo: Vec3d) -> Option<ScatterSample> {
         None
     }
 
-    fn emitted(&self, u: f64, v: f64, p: Point3d) -> Color3d {
-        self.emit.eval(u, v, p)
+    fn emitted(&self, hit_record: &HitRecord, _wo: Vec3d) -> Color3d {
+        emission(&self.emit, self.two_sided, hit_record)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+// Adds emission to any material, which keeps scattering light like a glowing hot plate.
+pub struct Emissive<M: Material, T: Texture> {
+    pub material: M,
+    emit: T,
+    two_sided: bool
+}
+
+impl<M: Material, T: Texture> Emissive<M, T> {
+    pub fn new(material: M, emit: T) -> Self {
+        Self { material, emit, two_sided: true }
+    }
+
+    pub fn one_sided(mut self) -> Self {
+        self.two_sided = false;
+        self
+    }
+}
+
+impl<M: Material, T: Texture> Material for Emissive<M, T> {
+    fn eval(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> Color3d {
+        self.material.eval(hit_record, wi, wo)
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> f64 {
+        self.material.pdf(hit_record, wi, wo)
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+        self.material.sample(hit_record, wo)
+    }
+
+    fn emitted(&self, hit_record: &HitRecord, wo: Vec3d) -> Color3d {
+        self.material.emitted(hit_record, wo) + emission(&self.emit, self.two_sided, hit_record)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
     }
 }
 
@@ -173,15 +588,119 @@ impl Isotropic<SolidColor> {
     }
 }
 
+// The phase function of a medium, scattering is uniform over the sphere and has no cosine term.
 impl<T> Material for Isotropic<T>
 where T: Texture {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Color3d, Ray)> {
-        let scattered =
-            Ray::new_with_time(hit_record.point, Vec3d::random_in_unit_sphere(), ray_in.time());
-        let attenuation = self.albedo.eval(hit_record.u, hit_record.v, hit_record.point);
+    fn eval(&self, hit_record: &HitRecord, _wi: Vec3d, _wo: Vec3d) -> Color3d {
+        self.albedo.eval(hit_record.u, hit_record.v, hit_record.point) * uniform_sphere_pdf()
+    }
 
-        Some((attenuation, scattered))
+    fn pdf(&self, _hit_record: &HitRecord, _wi: Vec3d, _wo: Vec3d) -> f64 {
+        uniform_sphere_pdf()
+    }
+
+    fn sample(&self, hit_record: &HitRecord, _wo: Vec3d) -> Option<ScatterSample> {
+        Some(ScatterSample {
+            direction: Vec3d::random_unit_vector(),
+            weight: self.albedo.eval(hit_record.u, hit_record.v, hit_record.point),
+            pdf: uniform_sphere_pdf(),
+            lobe: Lobe::Diffuse
+        })
     }
 
     no_emission!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::util::seed_random;
+
+    // The direction towards the viewer `degrees` from the normal and a hit on the plane z = 0, from
+    // outside the glass for `outward_z` 1 and from inside for -1.
+    fn hit_at(material: &(dyn Material + Send + Sync), outward_z: f64, degrees: f64) -> (Vec3d, HitRecord<'_>) {
+        let wo = Vec3d::new(degrees.to_radians().sin(), 0.0, degrees.to_radians().cos());
+        let ray = Ray::new(wo, -wo);
+        let hit_record = HitRecord::new_with_face_normal(
+            1.0, Vec3d::zero(), 0.0, 0.0, Vec3d::new(0.0, 0.0, outward_z), material, &ray
+        );
+        (wo, hit_record)
+    }
+
+    fn sample_seeded(material: &dyn Material, hit_record: &HitRecord, wo: Vec3d, seed: u64) -> Option<ScatterSample> {
+        seed_random(seed, 0);
+        material.sample(hit_record, wo)
+    }
+
+    #[inline]
+    fn relative_difference(a: f64, b: f64) -> f64 {
+        (a - b).abs() / a.abs().max(b.abs()).max(1e-12)
+    }
+
+    #[test]
+    fn rough_dielectric_without_roughness_matches_dielectric() {
+        let dielectric = Dielectric::new(1.5);
+        let rough = RoughDielectric::new(1.5, 0.0, 0.0);
+        let (mut reflected, mut refracted) = (0, 0);
+        // From outside and from inside the glass, up to past the critical angle.
+        for &outward_z in &[1.0, -1.0] {
+            for &degrees in &[0.0, 20.0, 40.0, 60.0] {
+                let (wo, hit_record) = hit_at(&dielectric, outward_z, degrees);
+                for seed in 0..32 {
+                    let expected = sample_seeded(&dielectric, &hit_record, wo, seed).unwrap();
+                    let sample = sample_seeded(&rough, &hit_record, wo, seed).unwrap();
+                    assert!((sample.direction - expected.direction).near_zero());
+                    assert!((sample.pdf - expected.pdf).abs() < 1e-12);
+                    assert!((sample.weight - expected.weight).near_zero());
+                    assert!(sample.lobe.is_specular() && expected.lobe.is_specular());
+                    if sample.direction.dot(&hit_record.normal) > 0.0 { reflected += 1 } else { refracted += 1 }
+                }
+            }
+        }
+        assert!(reflected > 0 && refracted > 0);
+    }
+
+    #[test]
+    fn slightly_rough_dielectric_matches_fresnel_and_its_own_density() {
+        const SAMPLES: u64 = 4000;
+        let rough = RoughDielectric::new(1.5, 0.04, 0.04);
+        assert!(!rough.is_specular());
+        for &(outward_z, degrees) in &[(1.0, 0.0), (1.0, 30.0), (1.0, 60.0), (-1.0, 20.0), (-1.0, 35.0)] {
+            let (wo, hit_record) = hit_at(&rough, outward_z, degrees);
+            let (mut reflected, mut weight) = (0, 0.0);
+            for seed in 0..SAMPLES {
+                // Directions no visible microfacet connects are rare and carry no energy.
+                let sample = match sample_seeded(&rough, &hit_record, wo, seed) {
+                    Some(sample) => sample,
+                    None => continue
+                };
+                assert!(!sample.lobe.is_specular());
+                if sample.direction.dot(&hit_record.normal) > 0.0 {
+                    reflected += 1;
+                }
+                weight += sample.weight.x;
+
+                // Evaluating the sampled direction gives back its density and weight.
+                let pdf = rough.pdf(&hit_record, sample.direction, wo);
+                let f = rough.eval(&hit_record, sample.direction, wo);
+                assert!(relative_difference(pdf, sample.pdf) < 1e-6, "{} against {}", pdf, sample.pdf);
+                assert!(relative_difference(f.x / pdf, sample.weight.x) < 1e-6,
+                        "{:?} against {:?}", f / pdf, sample.weight);
+            }
+
+            // The split between reflection and transmission is the Fresnel reflectance of the
+            // smooth interface, within the noise of the samples.
+            let eta = if outward_z > 0.0 { 1.5 } else { 1.0 / 1.5 };
+            let reflectance = fresnel_dielectric(degrees.to_radians().cos(), eta);
+            let fraction = reflected as f64 / SAMPLES as f64;
+            let tolerance = 4.0 * (reflectance * (1.0 - reflectance) / SAMPLES as f64).sqrt() + 0.005;
+            assert!((fraction - reflectance).abs() < tolerance,
+                    "{} degrees: reflected {} against {}", degrees, fraction, reflectance);
+            // Almost smooth glass neither loses nor adds energy, the weights are the ratio of the
+            // BSDF to the density of the directions actually sampled.
+            let mean_weight = weight / SAMPLES as f64;
+            assert!((mean_weight - 1.0).abs() < 0.01, "{} degrees: mean weight {}", degrees, mean_weight);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::f64::consts::{PI, TAU};
+
+use crate::vec3::Vec3d;
+use crate::color::Color3d;
+
+// Directions here are in the local shading frame, the normal is +z.
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals with Smith masking-shadowing.
+#[derive(Copy, Clone, Debug)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64
+}
+
+// Narrowest width along either axis, a surface smooth along only one of them would otherwise
+// divide by zero. Smooth along both is handled by `is_smooth`.
+const MIN_ALPHA: f64 = 1e-4;
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x: alpha_x.max(MIN_ALPHA), alpha_y: alpha_y.max(MIN_ALPHA) }
+    }
+
+    // Perceptually linear roughness in [0, 1] to the width of the distribution.
+    #[inline]
+    pub fn roughness_to_alpha(roughness: f64) -> f64 {
+        roughness * roughness
+    }
+
+    // Below this width along both axes the surface is rendered as perfectly smooth.
+    #[inline]
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: Vec3d) -> f64 {
+        let x = wm.x / self.alpha_x;
+        let y = wm.y / self.alpha_y;
+        let denominator = x * x + y * y + wm.z * wm.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * denominator * denominator)
+    }
+
+    fn lambda(&self, w: Vec3d) -> f64 {
+        if w.z == 0.0 {
+            return f64::INFINITY
+        }
+        let x = self.alpha_x * w.x;
+        let y = self.alpha_y * w.y;
+        let tan2_theta_alpha2 = (x * x + y * y) / (w.z * w.z);
+        0.5 * ((1.0 + tan2_theta_alpha2).sqrt() - 1.0)
+    }
+
+    pub fn g1(&self, w: Vec3d) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: Vec3d, wi: Vec3d) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the normals visible from `w`.
+    pub fn visible_d(&self, w: Vec3d, wm: Vec3d) -> f64 {
+        let cos_theta = w.z.abs();
+        if cos_theta == 0.0 {
+            return 0.0
+        }
+        self.g1(w) / cos_theta * self.d(wm) * w.dot(&wm).abs()
+    }
+
+    // Density of `sample_visible` after reflecting `wo` about the sampled normal.
+    pub fn reflection_pdf(&self, wo: Vec3d, wm: Vec3d) -> f64 {
+        let cos_om = wo.dot(&wm).abs();
+        if cos_om == 0.0 {
+            return 0.0
+        }
+        self.visible_d(wo, wm) / (4.0 * cos_om)
+    }
+
+    // A normal visible from `w`, from Heitz, "Sampling the GGX Distribution of Visible Normals".
+    pub fn sample_visible(&self, w: Vec3d, u: (f64, f64)) -> Vec3d {
+        let flip = w.z < 0.0;
+        let w = if flip { -w } else { w };
+
+        // Stretch to the hemisphere configuration.
+        let wh = Vec3d::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalized();
+        let length_squared = wh.x * wh.x + wh.y * wh.y;
+        let t1 = if length_squared > 0.0 {
+            Vec3d::new(-wh.y, wh.x, 0.0) / length_squared.sqrt()
+        } else {
+            Vec3d::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // Uniform point on the disc, warped to the projection of the visible hemisphere.
+        let r = u.0.sqrt();
+        let phi = TAU * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + wh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * wh;
+
+        let wm = Vec3d::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalized();
+        if flip { -wm } else { wm }
+    }
+}
+
+#[inline]
+pub fn reflect(wo: Vec3d, n: Vec3d) -> Vec3d {
+    -wo + 2.0 * wo.dot(&n) * n
+}
+
+// Refracts `wi` through a surface with normal `n`, `eta` is the index of refraction on the side
+// `n` points away from over the other side. None on total internal reflection.
+pub fn refract(wi: Vec3d, n: Vec3d, eta: f64) -> Option<Vec3d> {
+    let (mut n, mut eta, mut cos_theta_i) = (n, eta, n.dot(&wi));
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+        n = -n;
+    }
+
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None
+    }
+
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wi / eta + (cos_theta_i / eta - cos_theta_t) * n)
+}
+
+// Unpolarized Fresnel reflectance of an interface between dielectrics, `eta` is the index of
+// refraction on the side opposite to the incident direction over the incident side.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (mut cos_theta_i, mut eta) = (cos_theta_i.clamp(-1.0, 1.0), eta);
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+    }
+
+    let sin2_theta_i = 1.0 - cos_theta_i * cos_theta_i;
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0
+    }
+
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+// Fresnel reflectance of a conductor with complex index of refraction eta + ik, per channel.
+pub fn fresnel_conductor(cos_theta_i: f64, eta: Color3d, k: Color3d) -> Color3d {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_theta_i * cos_theta_i;
+        let sin2 = 1.0 - cos2;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_theta_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rp + rs)
+    };
+
+    Color3d::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
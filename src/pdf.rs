@@ -0,0 +1,90 @@
+use crate::vec3::{Point3d, Vec3d};
+use crate::hittable::Hittable;
+use crate::util::random_double;
+
+// A distribution of directions leaving a point.
+pub trait Pdf {
+    // Solid angle density of `generate` returning `direction`.
+    fn value(&self, direction: Vec3d) -> f64;
+
+    fn generate(&self) -> Vec3d;
+}
+
+impl<P: Pdf + ?Sized> Pdf for &P {
+    fn value(&self, direction: Vec3d) -> f64 {
+        (*self).value(direction)
+    }
+
+    fn generate(&self) -> Vec3d {
+        (*self).generate()
+    }
+}
+
+// Directions towards an object as seen from `origin`.
+pub struct HittablePdf<'a> {
+    pub origin: Point3d,
+    pub hittable: &'a (dyn Hittable + Send + Sync)
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(hittable: &'a (dyn Hittable + Send + Sync), origin: Point3d) -> Self {
+        Self { origin, hittable }
+    }
+}
+
+impl<'a> Pdf for HittablePdf<'a> {
+    fn value(&self, direction: Vec3d) -> f64 {
+        self.hittable.pdf_value(self.origin, direction)
+    }
+
+    fn generate(&self) -> Vec3d {
+        self.hittable.random(self.origin)
+    }
+}
+
+// Picks one of its components with probability proportional to its weight.
+pub struct MixturePdf<P: Pdf> {
+    components: Vec<(f64, P)>,
+    total_weight: f64
+}
+
+impl<P: Pdf> MixturePdf<P> {
+    pub fn new(components: Vec<(f64, P)>) -> Self {
+        let total_weight = components.iter().map(|(weight, _)| weight).sum();
+        Self { components, total_weight }
+    }
+
+    pub fn uniform<I: IntoIterator<Item = P>>(components: I) -> Self {
+        Self::new(components.into_iter().map(|pdf| (1.0, pdf)).collect())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.total_weight <= 0.0
+    }
+}
+
+impl<P: Pdf> Pdf for MixturePdf<P> {
+    fn value(&self, direction: Vec3d) -> f64 {
+        if self.is_empty() {
+            return 0.0
+        }
+
+        self.components.iter()
+            .map(|(weight, pdf)| weight * pdf.value(direction))
+            .sum::<f64>() / self.total_weight
+    }
+
+    fn generate(&self) -> Vec3d {
+        let mut target = random_double() * self.total_weight;
+        for (weight, pdf) in &self.components {
+            if target < *weight {
+                return pdf.generate()
+            }
+            target -= weight;
+        }
+
+        // Rounding left the target past the last component.
+        self.components.last().map_or(Vec3d::new(1.0, 0.0, 0.0), |(_, pdf)| pdf.generate())
+    }
+}
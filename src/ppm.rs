@@ -1,11 +1,17 @@
-use crate::color::{Color3d, write_color, corrected_color};
-use std::io::Write;
+use crate::color::{Color3d, linear_color};
+use crate::tone_mapping::ToneMapping;
+use crate::exr::write_exr;
+use std::io::{Write, BufWriter};
+use image::{ImageResult, Rgb};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::hdr::HdrEncoder;
 
 pub struct PPMFile {
     height: usize,
     width: usize,
     spp: usize,
-    pub buf: Vec<Color3d>
+    pub buf: Vec<Color3d>,
+    pub tone_mapping: ToneMapping
 }
 
 impl PPMFile {
@@ -18,12 +24,14 @@ impl PPMFile {
     pub fn create(height: usize,
                   width: usize,
                   spp: usize,
-                  buf: Vec<Color3d>) -> PPMFile {
+                  buf: Vec<Color3d>,
+                  tone_mapping: ToneMapping) -> PPMFile {
         PPMFile {
             height,
             width,
             spp,
-            buf
+            buf,
+            tone_mapping
         }
     }
 
@@ -32,9 +40,7 @@ impl PPMFile {
         let mut fp = Self::open_file(file_name)?;
 
         fp.write_all(format!("P6\n{} {}\n255\n", self.width, self.height).as_bytes())?;
-        for c in self.buf {
-            write_color(&mut fp, &c, self.spp)?
-        }
+        fp.write_all(&self.display_colors())?;
 
         Ok(())
     }
@@ -43,11 +49,43 @@ impl PPMFile {
         image::RgbImage::from_raw(
             self.width as u32,
             self.height as u32,
-            self.buf.iter()
-                .flat_map(|&p| corrected_color(p, self.spp).to_vec()).collect()
+            self.display_colors()
         ).unwrap()
     }
 
+    // Tone mapped 8-bit sRGB, three bytes per pixel.
+    fn display_colors(&self) -> Vec<u8> {
+        self.buf.iter()
+            .flat_map(|&p| self.tone_mapping.display_color(linear_color(p, self.spp)).to_vec())
+            .collect()
+    }
+
+    // Exposed but neither tone mapped nor clamped.
+    pub fn linear_buffer(&self) -> Vec<Color3d> {
+        self.buf.iter().map(|&p| self.tone_mapping.expose(linear_color(p, self.spp))).collect()
+    }
+
+    pub fn write_jpeg(&self, file_name: String, quality: u8) -> ImageResult<()> {
+        let mut fp = BufWriter::new(Self::open_file(file_name)?);
+        JpegEncoder::new_with_quality(&mut fp, quality).encode_image(&self.image_buffer())
+    }
+
+    // Radiance RGBE, linear and unclamped.
+    pub fn write_hdr(&self, file_name: String) -> ImageResult<()> {
+        let fp = BufWriter::new(Self::open_file(file_name)?);
+        let pixels: Vec<Rgb<f32>> = self.linear_buffer().iter()
+            .map(|c| Rgb([c.x as f32, c.y as f32, c.z as f32]))
+            .collect();
+        HdrEncoder::new(fp).encode(&pixels, self.width, self.height)
+    }
+
+    // OpenEXR with 32-bit float channels, linear and unclamped.
+    pub fn write_exr(&self, file_name: String) -> std::io::Result<()> {
+        let mut fp = BufWriter::new(Self::open_file(file_name)?);
+        write_exr(&mut fp, self.width, self.height, &self.linear_buffer())?;
+        fp.flush()
+    }
+
     property! { height: usize }
     property! { width: usize }
 }
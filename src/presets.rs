@@ -0,0 +1,123 @@
+use crate::scene::Scene;
+use crate::hittable_list::HittableList;
+use crate::vec3::{Point3d, Vec3d};
+use crate::camera::Camera;
+use crate::util::Angle;
+use crate::color::Color3d;
+
+// Built-in scenes, each one is a `HittableList` preset with the camera it was designed for.
+pub const SCENE_NAMES: [&str; 8] = [
+    "random",
+    "perlin_noise",
+    "principled",
+    "earth",
+    "sample_light",
+    "cornel_box",
+    "cornel_smoke",
+    "all_feature_box",
+];
+
+pub const DEFAULT_SCENE: &str = "all_feature_box";
+
+struct View {
+    look_from: Point3d,
+    look_at: Point3d,
+    fov: f64,
+    aperture: f64,
+    aspect_ratio: f64,
+    background: Color3d
+}
+
+impl View {
+    fn outdoor(look_from: Point3d, look_at: Point3d, aperture: f64) -> Self {
+        Self {
+            look_from, look_at,
+            fov: 20.0,
+            aperture,
+            aspect_ratio: 16.0 / 9.0,
+            background: Color3d::new(0.70, 0.80, 1.00)
+        }
+    }
+
+    fn dark_box(look_from: Point3d) -> Self {
+        Self {
+            look_from,
+            look_at: Point3d::new(278.0, 278.0, 0.0),
+            fov: 40.0,
+            aperture: 0.0,
+            aspect_ratio: 1.0,
+            background: Color3d::zero()
+        }
+    }
+}
+
+pub fn builtin_scene(name: &str) -> Option<Scene> {
+    let (world, view, spp) = match name {
+        "random" => (
+            HittableList::random(),
+            View::outdoor(Point3d::new(13.0, 2.0, 3.0), Point3d::zero(), 0.1),
+            500
+        ),
+        "perlin_noise" => (
+            HittableList::perlin_noise(),
+            View::outdoor(Point3d::new(13.0, 2.0, 3.0), Point3d::zero(), 0.0),
+            100
+        ),
+        "principled" => (
+            HittableList::principled(),
+            View::outdoor(Point3d::new(0.0, 3.0, 20.0), Point3d::new(0.0, 1.0, 0.0), 0.0),
+            500
+        ),
+        "earth" => (
+            HittableList::earth(),
+            View::outdoor(Point3d::new(13.0, 2.0, 3.0), Point3d::zero(), 0.0),
+            100
+        ),
+        "sample_light" => (
+            HittableList::sample_light(),
+            View {
+                background: Color3d::zero(),
+                ..View::outdoor(Point3d::new(26.0, 3.0, 6.0), Point3d::new(0.0, 2.0, 0.0), 0.0)
+            },
+            400
+        ),
+        "cornel_box" => (
+            HittableList::cornel_box(),
+            View::dark_box(Point3d::new(278.0, 278.0, -800.0)),
+            10000
+        ),
+        "cornel_smoke" => (
+            HittableList::cornel_smoke(),
+            View::dark_box(Point3d::new(278.0, 278.0, -800.0)),
+            10000
+        ),
+        "all_feature_box" => (
+            HittableList::all_feature_box(),
+            View::dark_box(Point3d::new(478.0, 278.0, -600.0)),
+            10000
+        ),
+        _ => return None
+    };
+
+    let image_width = 800;
+    let image_height = (image_width as f64 / view.aspect_ratio) as usize;
+    let camera = Camera::new_with_shutter(
+        view.look_from,
+        view.look_at,
+        Vec3d::new(0.0, 1.0, 0.0),
+        view.aspect_ratio,
+        Angle::DegAngle(view.fov),
+        view.aperture,
+        10.0,
+        0.0, 1.0
+    );
+
+    Some(Scene::new(
+        image_height,
+        image_width,
+        world,
+        camera,
+        spp,
+        view.background,
+    ))
+}
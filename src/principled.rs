@@ -0,0 +1,258 @@
+use std::f64::consts::PI;
+
+use crate::hittable::HitRecord;
+use crate::color::Color3d;
+use crate::vec3::Vec3d;
+use crate::util::{random_double, random_2d};
+use crate::texture::Texture;
+use crate::material::{Material, RoughDielectric, ScatterSample, Lobe};
+use crate::microfacet::{TrowbridgeReitz, reflect};
+use crate::vec3d_extensions::{OrthonormalBasis, cosine_hemisphere_pdf};
+use crate::environment::luminance;
+
+// One material for most surfaces after Burley, "Physically Based Shading at Disney": a diffuse
+// base with sheen, a GGX specular layer, a clear coat on top and rough glass-like transmission.
+// Scalar parameters are in [0, 1] and read from the mean of the channels of their textures.
+//
+// Parameters left out keep their defaults with struct update syntax:
+//   Principled { metallic: Box::new(1.0), roughness: Box::new(0.3), ..Principled::new(color) }
+pub struct Principled {
+    pub base_color: Box<dyn Texture>,
+    pub metallic: Box<dyn Texture>,
+    pub roughness: Box<dyn Texture>,
+    // Reflectance of the dielectric base at normal incidence, 0.5 is the usual 4%.
+    pub specular: Box<dyn Texture>,
+    // Tints the reflection of the dielectric base towards the base color.
+    pub specular_tint: Box<dyn Texture>,
+    // Grazing retro-reflection of cloth.
+    pub sheen: Box<dyn Texture>,
+    pub sheen_tint: Box<dyn Texture>,
+    pub clearcoat: Box<dyn Texture>,
+    pub clearcoat_roughness: Box<dyn Texture>,
+    pub transmission: Box<dyn Texture>,
+    pub index_refraction: f64,
+    pub emission: Option<Box<dyn Texture>>
+}
+
+impl Principled {
+    pub fn new<T: Texture + 'static>(base_color: T) -> Self {
+        Self {
+            base_color: Box::new(base_color),
+            metallic: Box::new(0.0),
+            roughness: Box::new(0.5),
+            specular: Box::new(0.5),
+            specular_tint: Box::new(0.0),
+            sheen: Box::new(0.0),
+            sheen_tint: Box::new(0.5),
+            clearcoat: Box::new(0.0),
+            clearcoat_roughness: Box::new(0.1),
+            transmission: Box::new(0.0),
+            index_refraction: 1.5,
+            emission: None
+        }
+    }
+
+    fn parameters(&self, hit_record: &HitRecord) -> Parameters {
+        let (u, v, p) = (hit_record.u, hit_record.v, hit_record.point);
+        let scalar = |texture: &dyn Texture| {
+            let value = texture.eval(u, v, p);
+            ((value.x + value.y + value.z) / 3.0).clamp(0.0, 1.0)
+        };
+
+        let base_color = self.base_color.eval(u, v, p);
+        let metallic = scalar(self.metallic.as_ref());
+        let roughness = scalar(self.roughness.as_ref());
+        let transmission = scalar(self.transmission.as_ref());
+
+        // The hue of the base color without its brightness.
+        let base_luminance = luminance(base_color);
+        let tint = if base_luminance > 0.0 { base_color / base_luminance } else { Color3d::one() };
+        let specular_color = Color3d::linear_interpolation(Color3d::one(), tint, scalar(self.specular_tint.as_ref()))
+            * (0.08 * scalar(self.specular.as_ref()));
+        let sheen_color = Color3d::linear_interpolation(Color3d::one(), tint, scalar(self.sheen_tint.as_ref()))
+            * scalar(self.sheen.as_ref());
+
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness).max(MIN_ALPHA);
+        let clearcoat_alpha = TrowbridgeReitz::roughness_to_alpha(scalar(self.clearcoat_roughness.as_ref())).max(MIN_ALPHA);
+
+        Parameters {
+            base_color,
+            roughness,
+            sheen_color,
+            specular_color,
+            metallic,
+            diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+            transmission_weight: (1.0 - metallic) * transmission,
+            clearcoat: scalar(self.clearcoat.as_ref()),
+            specular: TrowbridgeReitz::new(alpha, alpha),
+            clearcoat_distribution: TrowbridgeReitz::new(clearcoat_alpha, clearcoat_alpha),
+            glass: RoughDielectric::new(self.index_refraction, roughness, roughness)
+        }
+    }
+}
+
+// The specular lobes are kept glossy so that lights can be sampled, only glass may be smooth.
+const MIN_ALPHA: f64 = 1e-3;
+
+// Clear coats are a layer of polyurethane with an index of refraction of 1.5.
+const CLEARCOAT_F0: f64 = 0.04;
+
+// Parameters at a hit point.
+struct Parameters {
+    base_color: Color3d,
+    roughness: f64,
+    sheen_color: Color3d,
+    // Reflectance at normal incidence of the dielectric base.
+    specular_color: Color3d,
+    metallic: f64,
+    diffuse_weight: f64,
+    transmission_weight: f64,
+    clearcoat: f64,
+    specular: TrowbridgeReitz,
+    clearcoat_distribution: TrowbridgeReitz,
+    glass: RoughDielectric
+}
+
+#[inline]
+fn schlick_weight(cos_theta: f64) -> f64 {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0);
+    let m2 = m * m;
+    m2 * m2 * m
+}
+
+impl Parameters {
+    // Fresnel reflectance of the specular layer, metals and the dielectric base blended.
+    fn specular_fresnel(&self, cos_theta: f64) -> Color3d {
+        let weight = schlick_weight(cos_theta);
+        let schlick = |f0: Color3d| f0 + (Color3d::one() - f0) * weight;
+        schlick(self.base_color) * self.metallic + schlick(self.specular_color) * self.diffuse_weight
+    }
+
+    // Probabilities of sampling the diffuse, specular, clear coat and transmission lobes.
+    fn lobe_probabilities(&self, cos_theta_o: f64) -> Option<[f64; 4]> {
+        let specular = luminance(self.specular_fresnel(cos_theta_o)).max(0.1 * (1.0 - self.transmission_weight));
+        let clearcoat = self.clearcoat * (CLEARCOAT_F0 + (1.0 - CLEARCOAT_F0) * schlick_weight(cos_theta_o));
+        let weights = [self.diffuse_weight, specular, clearcoat, self.transmission_weight];
+
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            Some(weights.map(|weight| weight / total))
+        } else {
+            None
+        }
+    }
+
+    // Reflection of the diffuse, sheen, specular and clear coat lobes times the cosine, in the
+    // local frame.
+    fn eval_reflection(&self, wi: Vec3d, wo: Vec3d) -> Color3d {
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return Color3d::zero()
+        }
+        let wm = (wi + wo).normalized();
+        let cos_theta_d = wi.dot(&wm);
+
+        // Burley diffuse with retro-reflection at grazing angles for rough surfaces.
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_theta_d * cos_theta_d;
+        let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z)) * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+        let diffuse = self.base_color * (retro / PI) + self.sheen_color * schlick_weight(cos_theta_d);
+
+        let specular = self.specular_fresnel(cos_theta_d)
+            * (self.specular.d(wm) * self.specular.g(wo, wi) / (4.0 * wo.z));
+
+        let clearcoat_fresnel = CLEARCOAT_F0 + (1.0 - CLEARCOAT_F0) * schlick_weight(cos_theta_d);
+        let clearcoat = self.clearcoat * clearcoat_fresnel * self.clearcoat_distribution.d(wm)
+            * self.clearcoat_distribution.g(wo, wi) / (4.0 * wo.z);
+
+        diffuse * (self.diffuse_weight * wi.z) + specular + Color3d::only(clearcoat)
+    }
+
+    fn pdf_reflection(&self, wi: Vec3d, wo: Vec3d, probabilities: &[f64; 4]) -> f64 {
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return 0.0
+        }
+        let wm = (wi + wo).normalized();
+
+        probabilities[0] * cosine_hemisphere_pdf(wi.z)
+            + probabilities[1] * self.specular.reflection_pdf(wo, wm)
+            + probabilities[2] * self.clearcoat_distribution.reflection_pdf(wo, wm)
+    }
+
+    // Glass is evaluated in world space with the frame of the hit point.
+    fn eval_pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> Option<(Color3d, f64)> {
+        let basis = OrthonormalBasis::from_w(hit_record.normal);
+        let (wi_local, wo_local) = (basis.world_to_local(wi), basis.world_to_local(wo));
+        let probabilities = self.lobe_probabilities(wo_local.z)?;
+
+        let mut f = self.eval_reflection(wi_local, wo_local);
+        let mut pdf = self.pdf_reflection(wi_local, wo_local, &probabilities);
+        if self.transmission_weight > 0.0 {
+            f += self.glass.eval(hit_record, wi, wo) * self.base_color * self.transmission_weight;
+            pdf += probabilities[3] * self.glass.pdf(hit_record, wi, wo);
+        }
+
+        Some((f, pdf))
+    }
+}
+
+impl Material for Principled {
+    fn eval(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> Color3d {
+        self.parameters(hit_record).eval_pdf(hit_record, wi, wo).map_or(Color3d::zero(), |(f, _)| f)
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wi: Vec3d, wo: Vec3d) -> f64 {
+        self.parameters(hit_record).eval_pdf(hit_record, wi, wo).map_or(0.0, |(_, pdf)| pdf)
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: Vec3d) -> Option<ScatterSample> {
+        let parameters = self.parameters(hit_record);
+        let basis = OrthonormalBasis::from_w(hit_record.normal);
+        let wo_local = basis.world_to_local(wo);
+        let probabilities = parameters.lobe_probabilities(wo_local.z)?;
+
+        let u = random_double();
+        let (direction, lobe) = if u < probabilities[0] {
+            (basis.local(Vec3d::random_cosine_direction()), Lobe::Diffuse)
+        } else if u < probabilities[0] + probabilities[1] + probabilities[2] {
+            let distribution = if u < probabilities[0] + probabilities[1] {
+                parameters.specular
+            } else {
+                parameters.clearcoat_distribution
+            };
+            let wm = distribution.sample_visible(wo_local, random_2d());
+            (basis.local(reflect(wo_local, wm)), Lobe::Glossy)
+        } else {
+            let sample = parameters.glass.sample(hit_record, wo)?;
+            if sample.lobe.is_specular() {
+                // Smooth glass can not be combined with the other lobes.
+                return Some(ScatterSample {
+                    weight: sample.weight * parameters.base_color * (parameters.transmission_weight / probabilities[3]),
+                    pdf: sample.pdf * probabilities[3],
+                    ..sample
+                })
+            }
+            (sample.direction, Lobe::Glossy)
+        };
+
+        let (f, pdf) = parameters.eval_pdf(hit_record, direction, wo)?;
+        if pdf <= 0.0 {
+            return None
+        }
+
+        Some(ScatterSample {
+            direction,
+            weight: f / pdf,
+            pdf,
+            lobe
+        })
+    }
+
+    fn emitted(&self, hit_record: &HitRecord, _wo: Vec3d) -> Color3d {
+        self.emission.as_ref().map_or(Color3d::zero(), |emission| {
+            emission.eval(hit_record.u, hit_record.v, hit_record.point)
+        })
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emission.is_some()
+    }
+}
@@ -1,11 +1,12 @@
-use crate::material::Material;
-use crate::hittable::{Hittable, HitRecord};
+use crate::material::{Material, ScatterSample};
+use crate::hittable::{Hittable, HitRecord, area_pdf_to_solid_angle};
 use crate::ray::Ray;
 use crate::acceleration::aabb::AABB;
 use crate::vec3::{Point3d, Vec3d};
 use std::borrow::Borrow;
 use crate::hittable_list::HittableList;
 use crate::color::Color3d;
+use crate::util::random_2d;
 
 type Point2d = (f64, f64);
 
@@ -81,7 +82,7 @@ macro_rules! impl_rectangle {
         }
 
         impl<M: Material + Send + Sync> Hittable for $rect_name<M> {
-            fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+            fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
                 let t = (self.k - ray.origin().$z) / ray.direction().$z;
                 if t < t_min || t > t_max {
                     return None
@@ -99,13 +100,17 @@ macro_rules! impl_rectangle {
                 let u = ($x - $x0) / ($x1 - $x0);
                 let v = ($y - $y0) / ($y1 - $y0);
                 let outward_normal = Vec3d::new($norm_x, $norm_y, $norm_z);
+                let mut dpdu = Vec3d::zero();
+                dpdu.$x = $x1 - $x0;
+                let mut dpdv = Vec3d::zero();
+                dpdv.$y = $y1 - $y0;
                 Some(HitRecord::new_with_face_normal(
                     t, ray.at(t), u, v, outward_normal,
                     self.material.borrow(), ray
-                ))
+                ).with_tangents(dpdu, dpdv))
             }
 
-            fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+            fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
                 let ($x0, $y0) = self.p0;
                 let ($x1, $y1) = self.p1;
                 let $z = self.k;
@@ -115,6 +120,31 @@ macro_rules! impl_rectangle {
                     Point3d::new($max_box_x, $max_box_y, $max_box_z)
                 ))
             }
+
+            fn is_emitter(&self) -> bool {
+                self.material.is_emissive()
+            }
+
+            fn pdf_value(&self, origin: Point3d, direction: Vec3d) -> f64 {
+                let ($x0, $y0) = self.p0;
+                let ($x1, $y1) = self.p1;
+                let area = ($x1 - $x0) * ($y1 - $y0);
+
+                self.hit(&Ray::new(origin, direction), 0.001, f64::INFINITY)
+                    .map_or(0.0, |record| area_pdf_to_solid_angle(&record, direction, area))
+            }
+
+            fn random(&self, origin: Point3d) -> Vec3d {
+                let ($x0, $y0) = self.p0;
+                let ($x1, $y1) = self.p1;
+                let mut point = Point3d::zero();
+                let (u, v) = random_2d();
+                point.$x = $x0 + u * ($x1 - $x0);
+                point.$y = $y0 + v * ($y1 - $y0);
+                point.$z = self.k;
+
+                point - origin
+            }
         }
     };
 }
@@ -146,11 +176,11 @@ impl_rectangle! {
 #[derive(Copy, Clone)]
 pub struct DummyMaterial;
 impl Material for DummyMaterial {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Color3d, Ray)> {
+    fn sample(&self, _hit_record: &HitRecord, _wo: Vec3d) -> Option<ScatterSample> {
         None
     }
 
-    fn emitted(&self, u: f64, v: f64, p: Point3d) -> Color3d {
+    fn emitted(&self, _hit_record: &HitRecord, _wo: Vec3d) -> Color3d {
         Color3d::zero()
     }
 }
@@ -196,14 +226,14 @@ impl<M: Material + Send + Sync> RectBox<M> {
 }
 
 impl<M: Material + Send + Sync> Hittable for RectBox<M> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         self.sides.hit(ray, t_min, t_max).map(|mut record| {
             record.material = self.material.borrow();
             record
         })
     }
 
-    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
         Some(AABB::new(self.min, self.max))
     }
 }
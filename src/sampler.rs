@@ -0,0 +1,399 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::util::mix_bits;
+
+// Sample points of a pixel, one coordinate (dimension) at a time. Coordinates with the same
+// dimension are well distributed over the samples of a pixel, each dimension is used for the
+// same decision on every sample: the position in the pixel, on the lens, the time and then a
+// fixed range of dimensions per bounce.
+pub trait Sampler: Send + Sync {
+    // Number of leading dimensions covered, later ones are independent random numbers.
+    fn dimensions(&self) -> usize;
+
+    // Coordinate `dimension` of the sample `index` in [0, 1), pixels are decorrelated by `pixel_seed`.
+    fn get(&self, pixel_seed: u64, index: usize, dimension: usize) -> f64;
+}
+
+// Dimensions of the camera ray: two in the pixel, two on the lens, the time and one unused so
+// that every bounce starts at an even dimension.
+pub const CAMERA_DIMENSIONS: usize = 6;
+// Dimensions of every bounce, for the material and then for the light that is sampled. Either
+// picks a lobe or a light with the first one and a direction with the next pair.
+pub const SCATTER_DIMENSIONS: usize = 4;
+pub const LIGHT_DIMENSIONS: usize = 4;
+pub const BOUNCE_DIMENSIONS: usize = SCATTER_DIMENSIONS + LIGHT_DIMENSIONS;
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+#[inline]
+fn to_unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+// Element `i` of a random permutation of [0, l) chosen by `p`, Kensler, "Correlated Multi-Jittered
+// Sampling".
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+// Plain random numbers for every dimension.
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn dimensions(&self) -> usize {
+        0
+    }
+
+    fn get(&self, pixel_seed: u64, index: usize, dimension: usize) -> f64 {
+        to_unit(mix_bits(pixel_seed ^ mix_bits((index as u64) << 32 | dimension as u64)))
+    }
+}
+
+// Pairs of dimensions are a jittered grid of the largest square number of samples that fits,
+// visited in a different random order for every pair. Samples past the grid are independent.
+pub struct StratifiedSampler {
+    grid: usize
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize) -> Self {
+        let mut grid = (samples_per_pixel as f64).sqrt() as usize;
+        while (grid + 1) * (grid + 1) <= samples_per_pixel {
+            grid += 1;
+        }
+        Self { grid: grid.max(1) }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn dimensions(&self) -> usize {
+        usize::MAX
+    }
+
+    fn get(&self, pixel_seed: u64, index: usize, dimension: usize) -> f64 {
+        let pair_seed = mix_bits(pixel_seed ^ mix_bits((dimension / 2) as u64));
+        let jitter = IndependentSampler.get(pair_seed, index, dimension);
+        let cells = self.grid * self.grid;
+        if index >= cells {
+            return jitter
+        }
+
+        let cell = permutation_element(index as u32, cells as u32, pair_seed as u32) as usize;
+        let stratum = if dimension % 2 == 1 { cell / self.grid } else { cell % self.grid };
+        (stratum as f64 + jitter) / self.grid as f64
+    }
+}
+
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311
+];
+
+// The radical inverse of `a` in `base` with its digits permuted by nested random permutations,
+// after pbrt-v4. Digits past the end of `a` are scrambled too, down to the precision of a double
+// or as many as fit in 64 bits for the larger bases.
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, hash: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut inverse_base_m = 1.0;
+    let mut reversed_digits = 0u64;
+    while 1.0 - (base - 1) as f64 * inverse_base_m < 1.0 && reversed_digits < u64::MAX / base {
+        let digit_hash = mix_bits(hash ^ reversed_digits);
+        let digit = permutation_element((a % base) as u32, base as u32, digit_hash as u32) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inverse_base_m *= inverse_base;
+        a /= base;
+    }
+    (reversed_digits as f64 * inverse_base_m).min(ONE_MINUS_EPSILON)
+}
+
+// Dimension `d` is the radical inverse in the `d`-th prime, Owen scrambled per pixel.
+pub struct HaltonSampler;
+
+impl Sampler for HaltonSampler {
+    fn dimensions(&self) -> usize {
+        PRIMES.len()
+    }
+
+    fn get(&self, pixel_seed: u64, index: usize, dimension: usize) -> f64 {
+        let hash = mix_bits(pixel_seed ^ mix_bits(dimension as u64));
+        owen_scrambled_radical_inverse(PRIMES[dimension], index as u64, hash)
+    }
+}
+
+// Generator matrices of the first two Sobol dimensions as direction numbers.
+const fn sobol_directions(dimension: usize) -> [u32; 32] {
+    let mut directions = [0u32; 32];
+    let mut i = 0;
+    while i < 32 {
+        directions[i] = if dimension == 0 {
+            1 << (31 - i)
+        } else if i == 0 {
+            1 << 31
+        } else {
+            directions[i - 1] ^ (directions[i - 1] >> 1)
+        };
+        i += 1;
+    }
+    directions
+}
+
+const SOBOL_DIRECTIONS: [[u32; 32]; 2] = [sobol_directions(0), sobol_directions(1)];
+
+fn sobol(mut index: u32, dimension: usize) -> u32 {
+    let mut result = 0;
+    let mut bit = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= SOBOL_DIRECTIONS[dimension][bit];
+        }
+        index >>= 1;
+        bit += 1;
+    }
+    result
+}
+
+// Burley, "Practical Hash-based Owen Scrambling".
+#[inline]
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+#[inline]
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Pairs of dimensions are the first two Sobol dimensions, Owen scrambled, with the order of the
+// samples shuffled differently for every pair (Burley's padding). Any number of dimensions keeps
+// the quality of a 2D Sobol sequence, with powers of two samples per pixel at their best.
+pub struct SobolSampler;
+
+impl Sampler for SobolSampler {
+    fn dimensions(&self) -> usize {
+        usize::MAX
+    }
+
+    fn get(&self, pixel_seed: u64, index: usize, dimension: usize) -> f64 {
+        let pair_seed = mix_bits(pixel_seed ^ mix_bits((dimension / 2) as u64));
+        let shuffled = nested_uniform_scramble(index as u32, pair_seed as u32);
+        let value = sobol(shuffled, dimension % 2);
+        let scrambled = nested_uniform_scramble(value, mix_bits(pair_seed ^ (1 + dimension % 2) as u64) as u32);
+        (scrambled as f64 / (1u64 << 32) as f64).min(ONE_MINUS_EPSILON)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerType {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol
+}
+
+pub const SAMPLER_NAMES: [&str; 4] = ["independent", "stratified", "halton", "sobol"];
+
+impl SamplerType {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "independent" => Some(SamplerType::Independent),
+            "stratified" => Some(SamplerType::Stratified),
+            "halton" => Some(SamplerType::Halton),
+            "sobol" => Some(SamplerType::Sobol),
+            _ => None
+        }
+    }
+
+    pub fn build(&self, samples_per_pixel: usize) -> Arc<dyn Sampler> {
+        match self {
+            SamplerType::Independent => Arc::new(IndependentSampler),
+            SamplerType::Stratified => Arc::new(StratifiedSampler::new(samples_per_pixel)),
+            SamplerType::Halton => Arc::new(HaltonSampler),
+            SamplerType::Sobol => Arc::new(SobolSampler)
+        }
+    }
+}
+
+// The sample a thread is working on. `util::random_double` takes its numbers from here while a
+// range of dimensions is open and from the random number generator otherwise.
+struct SampleStream {
+    sampler: Option<Arc<dyn Sampler>>,
+    pixel_seed: u64,
+    index: usize,
+    dimension: usize,
+    end: usize
+}
+
+thread_local! {
+    static STREAM: RefCell<SampleStream> = RefCell::new(SampleStream {
+        sampler: None,
+        pixel_seed: 0,
+        index: 0,
+        dimension: 0,
+        end: 0
+    });
+}
+
+pub fn set_sampler(sampler: Option<Arc<dyn Sampler>>) {
+    STREAM.with(|stream| stream.borrow_mut().sampler = sampler);
+}
+
+pub fn start_pixel_sample(pixel_seed: u64, index: usize) {
+    STREAM.with(|stream| {
+        let mut stream = stream.borrow_mut();
+        stream.pixel_seed = pixel_seed;
+        stream.index = index;
+        stream.end = 0;
+    });
+}
+
+// Runs `f` with the dimensions [start, start + count) of the current sample, numbers drawn past
+// them are independent.
+pub fn with_dimensions<T>(start: usize, count: usize, f: impl FnOnce() -> T) -> T {
+    STREAM.with(|stream| {
+        let mut stream = stream.borrow_mut();
+        stream.dimension = start;
+        stream.end = start + count;
+    });
+    let result = f();
+    STREAM.with(|stream| stream.borrow_mut().end = 0);
+    result
+}
+
+// Samplers stratify the pairs (0, 1), (2, 3) and so on. A 2D sample starts at the next even
+// dimension, skipping one after an odd number of 1D samples, so both of its coordinates come
+// from the same pair.
+pub fn next_sample_2d() -> Option<(f64, f64)> {
+    STREAM.with(|stream| {
+        let stream = &mut *stream.borrow_mut();
+        let dimension = (stream.dimension + 1) & !1;
+        let value = match &stream.sampler {
+            Some(sampler) if dimension + 1 < stream.end && dimension + 1 < sampler.dimensions() => (
+                sampler.get(stream.pixel_seed, stream.index, dimension),
+                sampler.get(stream.pixel_seed, stream.index, dimension + 1)
+            ),
+            _ => return None
+        };
+        stream.dimension = dimension + 2;
+        Some(value)
+    })
+}
+
+pub fn next_sample() -> Option<f64> {
+    STREAM.with(|stream| {
+        let stream = &mut *stream.borrow_mut();
+        let dimension = stream.dimension;
+        let value = match &stream.sampler {
+            Some(sampler) if dimension < stream.end && dimension < sampler.dimensions() =>
+                sampler.get(stream.pixel_seed, stream.index, dimension),
+            _ => return None
+        };
+        stream.dimension += 1;
+        Some(value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{random_double, random_2d};
+
+    const SAMPLES: usize = 16;
+    const GRID: usize = 4;
+
+    // The cells of a GRID × GRID grid hit by one 2D draw per sample, in the light window of the
+    // second bounce after the light has been picked with a 1D draw.
+    fn light_direction_cells(sampler: Arc<dyn Sampler>, pixel_seed: u64) -> Vec<usize> {
+        set_sampler(Some(sampler));
+        let start = CAMERA_DIMENSIONS + BOUNCE_DIMENSIONS + SCATTER_DIMENSIONS;
+        let cells = (0..SAMPLES).map(|index| {
+            start_pixel_sample(pixel_seed, index);
+            let (u, v) = with_dimensions(start, LIGHT_DIMENSIONS, || {
+                random_double();
+                random_2d()
+            });
+            (u * GRID as f64) as usize * GRID + (v * GRID as f64) as usize
+        }).collect();
+        set_sampler(None);
+        cells
+    }
+
+    #[test]
+    fn bounce_2d_draws_stay_stratified() {
+        let samplers: [(&str, Arc<dyn Sampler>); 2] = [
+            ("stratified", Arc::new(StratifiedSampler::new(SAMPLES))),
+            ("sobol", Arc::new(SobolSampler))
+        ];
+        for (name, sampler) in samplers.iter() {
+            for pixel_seed in 0..100 {
+                let mut cells = light_direction_cells(sampler.clone(), pixel_seed);
+                cells.sort_unstable();
+                cells.dedup();
+                assert_eq!(cells.len(), SAMPLES, "{} sampler, pixel {}", name, pixel_seed);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_in_the_unit_interval_and_repeatable() {
+        for sampler_type in [SamplerType::Independent, SamplerType::Stratified, SamplerType::Halton,
+                             SamplerType::Sobol].iter() {
+            let sampler = sampler_type.build(SAMPLES);
+            for index in 0..SAMPLES * 2 {
+                for dimension in 0..PRIMES.len() {
+                    let value = sampler.get(42, index, dimension);
+                    assert!((0.0..1.0).contains(&value), "{:?} gave {}", sampler_type, value);
+                    assert_eq!(value, sampler.get(42, index, dimension));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draws_past_the_window_are_independent() {
+        set_sampler(Some(Arc::new(SobolSampler)));
+        start_pixel_sample(3, 0);
+        let (inside, outside) = with_dimensions(0, 3, || {
+            (next_sample_2d(), (next_sample(), next_sample_2d()))
+        });
+        assert!(inside.is_some());
+        assert_eq!(outside, (Some(SobolSampler.get(3, 0, 2)), None));
+        set_sampler(None);
+    }
+}
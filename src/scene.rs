@@ -1,16 +1,25 @@
-use std::f64::INFINITY;
+use std::time::Duration;
+use std::sync::Arc;
 use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle, ProgressIterator};
 use rayon::prelude::*;
 
 use crate::camera::Camera;
 use crate::color::Color3d;
 use crate::hittable::Hittable;
-use crate::material::Material;
 use crate::ppm::PPMFile;
-use crate::ray::Ray;
-use crate::util::random_double;
-use crate::acceleration::bvh::BVH;
+use crate::util::{random_2d, seed_random, mix_bits};
+use crate::sampler::{self, Sampler, SamplerType, with_dimensions, CAMERA_DIMENSIONS};
+use crate::light::Light;
+use crate::environment::Environment;
+use crate::integrator::PathTracer;
+use crate::acceleration::bvh::BuildOptions;
+use crate::acceleration::linear_bvh::LinearBVH;
 use crate::hittable_list::HittableList;
+use crate::tone_mapping::ToneMapping;
+
+// Larger images are more likely a typo than an intent, and would not fit in memory anyway.
+pub const MAX_IMAGE_SIZE: usize = 1 << 16;
+pub const MAX_IMAGE_PIXELS: usize = 1 << 28;
 
 pub struct Scene {
     pub height: usize,
@@ -18,7 +27,18 @@ pub struct Scene {
     pub world: HittableList,
     pub camera: Camera,
     pub spp: usize,
-    background: Color3d
+    pub max_depth: usize,
+    pub background: Box<dyn Environment + Send + Sync>,
+    pub lights: Vec<Box<dyn Light + Send + Sync>>,
+    pub bvh_options: BuildOptions,
+    pub tone_mapping: ToneMapping,
+    // Width and height of the blocks of pixels rendered as one task.
+    pub tile_size: usize,
+    // Prints the render times of the tiles to stderr.
+    pub verbose: bool,
+    // Renders with the same seed are identical.
+    pub seed: u64,
+    pub sampler: SamplerType
 }
 
 impl Scene {
@@ -36,66 +56,141 @@ impl Scene {
             world,
             camera,
             spp,
-            background
+            max_depth: 50,
+            background: Box::new(background),
+            lights: vec![],
+            bvh_options: BuildOptions::default(),
+            tone_mapping: ToneMapping::default(),
+            tile_size: 32,
+            verbose: false,
+            seed: 0,
+            sampler: SamplerType::Independent
+        }
+    }
+
+    // Changes the output resolution, the camera follows the new aspect ratio.
+    pub fn set_resolution(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.camera = self.camera.with_aspect_ratio(width as f64 / height as f64);
+    }
+
+    // Top level objects that are sampled directly when shading a surface.
+    pub fn emitters(&self) -> Vec<&(dyn Hittable + Send + Sync)> {
+        self.world.objects.iter()
+            .filter(|object| object.is_emitter())
+            .map(|object| object.as_ref())
+            .collect()
+    }
+
+    fn path_tracer<'a>(&'a self, bvh: &'a LinearBVH<'a>) -> PathTracer<'a, LinearBVH<'a>> {
+        PathTracer {
+            world: bvh,
+            emitters: self.emitters(),
+            lights: &self.lights,
+            background: self.background.as_ref(),
+            max_depth: self.max_depth
         }
     }
 
     #[inline]
-    fn render_single(&self, bvh: &BVH, i: usize, j: usize) -> Color3d {
-        let u = (i as f64 + random_double()) / (self.width - 1) as f64;
-        let v = 1.0 - (j as f64 + random_double()) / (self.height - 1) as f64;
-        let r = self.camera.get_ray(u, v);
+    fn render_single(&self, tracer: &PathTracer<LinearBVH>, i: usize, j: usize, sample: usize) -> Color3d {
+        let pixel = self.get_pixel_index(i, j);
+        seed_random(self.seed, (pixel * self.spp + sample) as u64);
+        sampler::start_pixel_sample(mix_bits(self.seed ^ mix_bits(pixel as u64)), sample);
+        let r = with_dimensions(0, CAMERA_DIMENSIONS, || {
+            let (du, dv) = random_2d();
+            let u = (i as f64 + du) / (self.width - 1) as f64;
+            let v = 1.0 - (j as f64 + dv) / (self.height - 1) as f64;
+            self.camera.get_ray(u, v)
+        });
+
+        tracer.ray_color(&r)
+    }
 
-        ray_color(&r, bvh, &self.background, 50)
+    fn generate_bvh(&self) -> LinearBVH<'_> {
+        LinearBVH::new(&self.world.objects,
+                       self.camera.shutter_open,
+                       self.camera.shutter_close,
+                       self.bvh_options)
     }
 
-    fn generate_bvh(&self) -> BVH {
-        BVH::new(&self.world.objects,
-                 self.camera.shutter_open,
-                 self.camera.shutter_close)
+    // Square blocks of the image in reading order, smaller at the right and bottom edges.
+    fn tiles(&self) -> Vec<Tile> {
+        let size = self.tile_size;
+        (0..self.height).step_by(size).flat_map(|y0| {
+            (0..self.width).step_by(size).map(move |x0| Tile {
+                x0, y0,
+                x1: (x0 + size).min(self.width),
+                y1: (y0 + size).min(self.height),
+            })
+        }).collect()
     }
 
-    pub fn render(&self) -> Vec<Color3d> {
-        let bvh = self.generate_bvh();
-        let pb = self.get_progress_bar();
+    // Pixels of the tile in reading order. Samples of a pixel are summed in order, so the
+    // result of a tile does not depend on which thread renders it.
+    fn render_tile(&self, tracer: &PathTracer<LinearBVH>, sampler: &Arc<dyn Sampler>, tile: &Tile) -> RenderedTile {
         let start_time = std::time::Instant::now();
-
-        let buf = (0..self.height).progress_with(pb).flat_map(|j| {
-            let bvh_borrow = &bvh;
-            (0..self.width).map(move |i| {
-                (0..self.spp).map(|_| self.render_single(bvh_borrow, i, j)).sum()
+        sampler::set_sampler(Some(sampler.clone()));
+        let pixels = (tile.y0..tile.y1).flat_map(|j| {
+            (tile.x0..tile.x1).map(move |i| {
+                (0..self.spp).map(|sample| self.render_single(tracer, i, j, sample)).sum()
             })
         }).collect();
-
-        println!("\nTracing ({}*{}, spp={}) finished in {}.",
-                 self.width, self.height, self.spp,
-                 indicatif::FormattedDuration(start_time.elapsed()));
-
-        buf
+        sampler::set_sampler(None);
+        RenderedTile { pixels, time: start_time.elapsed() }
     }
 
-    pub fn render_parallel(&self) -> Vec<Color3d> {
+    fn render_tiles(&self, parallel: bool) -> Vec<Color3d> {
         let bvh_start = std::time::Instant::now();
         println!("Building BVH");
         let bvh = self.generate_bvh();
-        println!("BVH built in {}ms.", bvh_start.elapsed().as_micros());
+        println!("BVH built in {}ms: {}.", bvh_start.elapsed().as_millis(), bvh.stats());
+        let tracer = self.path_tracer(&bvh);
+        let sampler = self.sampler.build(self.spp);
+        let tiles = self.tiles();
+        let pb = self.get_progress_bar(tiles.len());
         let start_time = std::time::Instant::now();
-        let pb = self.get_progress_bar();
-        let result = (0..self.height).into_par_iter().progress_with(pb).flat_map(|j| {
-            let bvh_borrow = &bvh;
-            (0..self.width).into_par_iter().map(move |i| {
-                (0..self.spp).into_par_iter().map(|_| self.render_single(bvh_borrow, i, j)).sum::<Color3d>()
-            })
-        }).collect();
+
+        let rendered: Vec<RenderedTile> = if parallel {
+            // One tile per task, idle workers steal the tiles that are left.
+            tiles.par_iter().with_max_len(1).progress_with(pb)
+                .map(|tile| self.render_tile(&tracer, &sampler, tile))
+                .collect()
+        } else {
+            tiles.iter().progress_with(pb)
+                .map(|tile| self.render_tile(&tracer, &sampler, tile))
+                .collect()
+        };
 
         println!("\nTracing ({}*{}, spp={}) finished in {}.",
                  self.width, self.height, self.spp,
                  indicatif::FormattedDuration(start_time.elapsed()));
-        result
+        if self.verbose {
+            print_tile_times(&tiles, &rendered);
+        }
+
+        let mut buf = vec![Color3d::zero(); self.width * self.height];
+        for (tile, rendered) in tiles.iter().zip(rendered) {
+            let width = tile.x1 - tile.x0;
+            for (row, pixels) in rendered.pixels.chunks(width).enumerate() {
+                let start = self.get_pixel_index(tile.x0, tile.y0 + row);
+                buf[start..start + width].copy_from_slice(pixels);
+            }
+        }
+        buf
+    }
+
+    pub fn render(&self) -> Vec<Color3d> {
+        self.render_tiles(false)
     }
 
-    fn get_progress_bar(&self) -> ProgressBar {
-        ProgressBar::new(self.height as u64)
+    pub fn render_parallel(&self) -> Vec<Color3d> {
+        self.render_tiles(true)
+    }
+
+    fn get_progress_bar(&self, tiles: usize) -> ProgressBar {
+        ProgressBar::new(tiles as u64)
             .with_style(
                 ProgressStyle::default_bar()
                 .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>4}/{len:4} ({eta})")
@@ -109,28 +204,37 @@ impl Scene {
 
     pub fn get_ppm_file(&self) -> PPMFile {
         PPMFile::create(
-            self.height, self.width, self.spp, self.render(),
+            self.height, self.width, self.spp, self.render(), self.tone_mapping
         )
     }
 
     pub fn get_ppm_file_parallel(&self) -> PPMFile {
         PPMFile::create(
-            self.height, self.width, self.spp, self.render_parallel()
+            self.height, self.width, self.spp, self.render_parallel(), self.tone_mapping
         )
     }
 }
 
-pub fn ray_color<H: Hittable>(ray: &Ray, world: &H, background: &Color3d, max_depth: i32) -> Color3d {
-    if max_depth <= 0 {
-        Color3d::zero()
-    } else if let Some(hit) = world.hit(&ray, 0.001, INFINITY) {
-        let emitted = hit.material.emitted(hit.u, hit.v, hit.point);
-        if let Some((color, scattered)) = hit.material.scatter(&ray, &hit) {
-            emitted + color * ray_color(&scattered, world, background, max_depth - 1)
-        } else {
-            emitted
-        }
-    } else {
-        *background
+struct Tile {
+    x0: usize, y0: usize,
+    x1: usize, y1: usize
+}
+
+struct RenderedTile {
+    pixels: Vec<Color3d>,
+    time: Duration
+}
+
+// The mean and the slowest tiles show where the time of a render goes.
+fn print_tile_times(tiles: &[Tile], rendered: &[RenderedTile]) {
+    let total: Duration = rendered.iter().map(|tile| tile.time).sum();
+    let slowest = rendered.iter().enumerate().max_by_key(|(_, tile)| tile.time);
+    if let Some((index, slowest)) = slowest {
+        let tile = &tiles[index];
+        eprintln!("{} tiles, {}ms on average, slowest at ({}, {})-({}, {}) in {}ms.",
+                  tiles.len(),
+                  total.as_millis() / tiles.len() as u128,
+                  tile.x0, tile.y0, tile.x1, tile.y1,
+                  slowest.time.as_millis());
     }
 }
@@ -0,0 +1,953 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use image::DynamicImage;
+
+use crate::scene::{Scene, MAX_IMAGE_SIZE, MAX_IMAGE_PIXELS};
+use crate::camera::Camera;
+use crate::color::Color3d;
+use crate::vec3::{Point3d, Vec3d};
+use crate::util::{Angle, seed_scene_random};
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::principled::Principled;
+use crate::layered::{MixMaterial, Coated};
+use crate::bump::{NormalMap, BumpMap};
+use crate::material::{Material, Diffuse, Metal, Conductor, CONDUCTOR_PRESETS, Dielectric, RoughDielectric, DiffuseLight, Emissive, Isotropic};
+use crate::texture::{Texture, SolidColor, CheckerTexture, NoiseTexture};
+use crate::image_texture::ImageTexture;
+use crate::sphere::{Sphere, MovingSphere};
+use crate::rectangle::{XYRect, XZRect, YZRect, RectBox, DummyMaterial};
+use crate::subsurface::ConstantMedium;
+use crate::triangle::Triangle;
+use crate::wavefront::ObjModel;
+use crate::transformations::Transformable;
+use crate::light::{Light, PointLight, SpotLight, DirectionalLight};
+use crate::environment::{Environment, EnvironmentMap, GradientSky, PreethamSky};
+use crate::tone_mapping::{ToneMapping, ToneOperator, DEFAULT_WHITE};
+use crate::sampler::SamplerType;
+
+// A scene file is a sequence of directives, one per line. `#` starts a comment.
+//
+//   image width=800 aspect_ratio=1.0 spp=100 max_depth=50 background=0,0,0 seed=7
+//   image exposure=-0.5 tone_map=extended_reinhard white=8 sampler=sobol
+//   camera look_from=278,278,-800 look_at=278,278,0 fov=40
+//   texture checker checker even=0.2,0.3,0.1 odd=0.9
+//   material ground diffuse albedo=checker
+//   material gold conductor metal=gold roughness=0.2
+//   material frosted rough_dielectric ior=1.5 roughness=0.3
+//   material wine dielectric ior=1.35 absorption=0.02,0.2,0.15
+//   material paint principled base_color=0.8,0.1,0.1 roughness=0.4 clearcoat=1
+//   material rusty mix a=steel b=rust mask=rust_mask
+//   material varnished coated base=wood ior=1.5 roughness=0.05
+//   material bricks normal_map base=brick_color map=brick_normals
+//   material plaster bump_map base=white height=noise scale=0.5
+//   material panel diffuse_light emit=15 one_sided=true
+//   xz_rect from=213,227 to=343,332 k=554 material=panel flip_face=true
+//   material hot_plate metal albedo=0.5 fuzz=0.3 emit=2,0.4,0.1
+//   sphere center=0,-1000,0 radius=1000 material=ground
+//   box min=0,0,0 max=165,330,165 material=white rotate_y=15 translate=265,0,295
+//   obj file=bunny.obj scale=100 material=white
+//   point_light position=278,500,278 intensity=50000
+//   spot_light position=278,500,278 look_at=278,0,278 intensity=80000 angle=30 falloff=20
+//   directional_light direction=-1,-2,1 irradiance=3
+//   environment file=studio.hdr intensity=1.5 rotation=90
+//   sky sun_direction=1,0.5,0.3 turbidity=3 sun=5 sun_radius=0.27
+//   gradient bottom=1 top=0.5,0.7,1
+//
+// Textures and materials are named and referenced by shapes. Wherever a texture is expected,
+// a literal color (`r,g,b` or a single gray value) can be used instead of a name.
+// Transformations on a shape are applied in the order they are written.
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(std::io::Error),
+    Syntax { line: usize, message: String }
+}
+
+impl Display for SceneFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneFileError::Io(error) => write!(f, "{}", error),
+            SceneFileError::Syntax { line, message } => write!(f, "line {}: {}", line, message)
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+impl From<std::io::Error> for SceneFileError {
+    fn from(error: std::io::Error) -> Self {
+        SceneFileError::Io(error)
+    }
+}
+
+type ParseResult<T> = Result<T, SceneFileError>;
+
+// A given `seed` replaces the one of the `image` directive.
+pub fn load_scene(path: &str, seed: Option<u64>) -> ParseResult<Scene> {
+    let source = std::fs::read_to_string(path)?;
+    let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    parse_scene(&source, base_dir, seed)
+}
+
+pub fn parse_scene(source: &str, base_dir: &Path, seed: Option<u64>) -> ParseResult<Scene> {
+    let mut parser = SceneParser::new(base_dir, seed);
+    let mut line_count = 0;
+    for (index, line) in source.lines().enumerate() {
+        line_count = index + 1;
+        if let Some(directive) = Directive::parse(line_count, line)? {
+            parser.directive(&directive)?;
+            directive.finish()?;
+        }
+    }
+
+    parser.finish(line_count)
+}
+
+struct Directive<'a> {
+    line: usize,
+    keyword: &'a str,
+    positional: Vec<&'a str>,
+    arguments: Vec<(&'a str, &'a str)>,
+    used: RefCell<HashSet<&'a str>>
+}
+
+impl<'a> Directive<'a> {
+    fn parse(line: usize, text: &'a str) -> ParseResult<Option<Self>> {
+        let text = text.split('#').next().unwrap_or("");
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(None)
+        };
+
+        let mut positional = vec![];
+        let mut arguments: Vec<(&str, &str)> = vec![];
+        for token in tokens {
+            match token.find('=') {
+                Some(split) => {
+                    let (key, value) = (&token[..split], &token[split + 1..]);
+                    if key.is_empty() || value.is_empty() {
+                        return Err(syntax_error(line, format!("malformed argument `{}`", token)));
+                    }
+                    if arguments.iter().any(|&(k, _)| k == key) {
+                        return Err(syntax_error(line, format!("duplicated argument `{}`", key)));
+                    }
+                    arguments.push((key, value));
+                }
+                None if arguments.is_empty() => positional.push(token),
+                None => return Err(syntax_error(
+                    line, format!("expected `key=value` but found `{}`", token)))
+            }
+        }
+
+        Ok(Some(Self {
+            line, keyword, positional, arguments,
+            used: RefCell::new(HashSet::new())
+        }))
+    }
+
+    fn error<T>(&self, message: String) -> ParseResult<T> {
+        Err(syntax_error(self.line, message))
+    }
+
+    fn positional(&self, count: usize) -> ParseResult<&[&'a str]> {
+        if self.positional.len() != count {
+            self.error(format!("`{}` expects {} name(s) before its arguments, found {}",
+                               self.keyword, count, self.positional.len()))
+        } else {
+            Ok(&self.positional)
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        let (key, value) = *self.arguments.iter().find(|&&(k, _)| k == key)?;
+        self.used.borrow_mut().insert(key);
+        Some(value)
+    }
+
+    fn require(&self, key: &str) -> ParseResult<&'a str> {
+        match self.get(key) {
+            Some(value) => Ok(value),
+            None => self.error(format!("`{}` requires argument `{}`", self.keyword, key))
+        }
+    }
+
+    fn parse_number(&self, key: &str, value: &str) -> ParseResult<f64> {
+        value.parse().or_else(|_| self.error(format!("`{}` expects a number, found `{}`", key, value)))
+    }
+
+    fn parse_numbers(&self, key: &str, value: &str) -> ParseResult<Vec<f64>> {
+        value.split(',').map(|x| self.parse_number(key, x)).collect()
+    }
+
+    fn number(&self, key: &str) -> ParseResult<f64> {
+        self.parse_number(key, self.require(key)?)
+    }
+
+    fn positive_number(&self, key: &str) -> ParseResult<f64> {
+        let value = self.number(key)?;
+        if value <= 0.0 {
+            return self.error(format!("`{}` must be positive", key));
+        }
+        Ok(value)
+    }
+
+    fn number_or(&self, key: &str, default: f64) -> ParseResult<f64> {
+        self.get(key).map_or(Ok(default), |value| self.parse_number(key, value))
+    }
+
+    fn integer_or(&self, key: &str, default: usize) -> ParseResult<usize> {
+        self.get(key).map_or(Ok(default), |value| {
+            value.parse()
+                .or_else(|_| self.error(format!("`{}` expects a non-negative integer, found `{}`", key, value)))
+        })
+    }
+
+    fn parse_boolean(&self, key: &str, value: &str) -> ParseResult<bool> {
+        match value {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => self.error(format!("`{}` expects true or false, found `{}`", key, value))
+        }
+    }
+
+    fn boolean_or(&self, key: &str, default: bool) -> ParseResult<bool> {
+        self.get(key).map_or(Ok(default), |value| self.parse_boolean(key, value))
+    }
+
+    fn parse_vector(&self, key: &str, value: &str) -> ParseResult<Vec3d> {
+        match self.parse_numbers(key, value)?.as_slice() {
+            [x] => Ok(Vec3d::only(*x)),
+            [x, y, z] => Ok(Vec3d::new(*x, *y, *z)),
+            _ => self.error(format!("`{}` expects 1 or 3 comma separated numbers, found `{}`", key, value))
+        }
+    }
+
+    fn vector(&self, key: &str) -> ParseResult<Vec3d> {
+        self.parse_vector(key, self.require(key)?)
+    }
+
+    fn vector_or(&self, key: &str, default: Vec3d) -> ParseResult<Vec3d> {
+        self.get(key).map_or(Ok(default), |value| self.parse_vector(key, value))
+    }
+
+    fn pair(&self, key: &str) -> ParseResult<(f64, f64)> {
+        let value = self.require(key)?;
+        match self.parse_numbers(key, value)?.as_slice() {
+            [x, y] => Ok((*x, *y)),
+            _ => self.error(format!("`{}` expects 2 comma separated numbers, found `{}`", key, value))
+        }
+    }
+
+    fn pair_or(&self, key: &str, default: (f64, f64)) -> ParseResult<(f64, f64)> {
+        if self.get(key).is_some() { self.pair(key) } else { Ok(default) }
+    }
+
+    // Roughness along the two tangent directions, a single value for isotropic surfaces.
+    fn roughness_or(&self, key: &str, default: f64) -> ParseResult<(f64, f64)> {
+        let value = match self.get(key) {
+            Some(value) => value,
+            None => return Ok((default, default))
+        };
+        match self.parse_numbers(key, value)?.as_slice() {
+            [r] if (0.0..=1.0).contains(r) => Ok((*r, *r)),
+            [u, v] if (0.0..=1.0).contains(u) && (0.0..=1.0).contains(v) => Ok((*u, *v)),
+            _ => self.error(format!("`{}` expects 1 or 2 comma separated numbers between 0 and 1, found `{}`", key, value))
+        }
+    }
+
+    // Reports arguments that no one asked for, typos are the most common mistakes in scene files.
+    fn finish(&self) -> ParseResult<()> {
+        let used = self.used.borrow();
+        match self.arguments.iter().find(|(key, _)| !used.contains(key)) {
+            Some((key, _)) => self.error(format!("unknown argument `{}` for `{}`", key, self.keyword)),
+            None => Ok(())
+        }
+    }
+}
+
+fn syntax_error(line: usize, message: String) -> SceneFileError {
+    SceneFileError::Syntax { line, message }
+}
+
+#[derive(Clone)]
+enum TextureSpec {
+    Solid(Color3d),
+    Checker(Box<TextureSpec>, Box<TextureSpec>),
+    Noise(NoiseTexture),
+    Image(ImageTexture<DynamicImage>)
+}
+
+impl TextureSpec {
+    fn build(&self) -> Box<dyn Texture> {
+        match self {
+            TextureSpec::Solid(color) => Box::new(SolidColor::new(*color)),
+            TextureSpec::Checker(even, odd) => Box::new(CheckerTexture::new(even.build(), odd.build())),
+            TextureSpec::Noise(noise) => Box::new(noise.clone()),
+            TextureSpec::Image(image) => Box::new(image.clone())
+        }
+    }
+}
+
+#[derive(Clone)]
+enum MaterialSpec {
+    Diffuse(TextureSpec),
+    Metal { albedo: Color3d, fuzz: f64 },
+    Conductor(Conductor),
+    Dielectric(Dielectric),
+    RoughDielectric(RoughDielectric),
+    Principled(Box<PrincipledSpec>),
+    Mix { a: Box<MaterialSpec>, b: Box<MaterialSpec>, mask: TextureSpec },
+    Coated { base: Box<MaterialSpec>, index_refraction: f64, roughness: (f64, f64) },
+    NormalMap { base: Box<MaterialSpec>, map: TextureSpec },
+    BumpMap { base: Box<MaterialSpec>, height: TextureSpec, scale: f64 },
+    DiffuseLight { emit: TextureSpec, two_sided: bool },
+    Emissive { material: Box<MaterialSpec>, emit: TextureSpec, two_sided: bool },
+    Isotropic(TextureSpec)
+}
+
+impl MaterialSpec {
+    fn build(&self) -> Box<dyn Material + Send + Sync> {
+        match self {
+            MaterialSpec::Diffuse(albedo) => Box::new(Diffuse::new(albedo.build())),
+            MaterialSpec::Metal { albedo, fuzz } => Box::new(Metal { albedo: *albedo, fuzz: *fuzz }),
+            MaterialSpec::Conductor(conductor) => Box::new(conductor.clone()),
+            MaterialSpec::Dielectric(dielectric) => Box::new(dielectric.clone()),
+            MaterialSpec::RoughDielectric(dielectric) => Box::new(dielectric.clone()),
+            MaterialSpec::Principled(principled) => Box::new(principled.build()),
+            MaterialSpec::Mix { a, b, mask } => Box::new(MixMaterial::new(a.build(), b.build(), mask.build())),
+            MaterialSpec::Coated { base, index_refraction, roughness: (u, v) } =>
+                Box::new(Coated::new(base.build(), *index_refraction, *u, *v)),
+            MaterialSpec::NormalMap { base, map } => Box::new(NormalMap::new(base.build(), map.build())),
+            MaterialSpec::BumpMap { base, height, scale } =>
+                Box::new(BumpMap::new(base.build(), height.build(), *scale)),
+            MaterialSpec::DiffuseLight { emit, two_sided: true } => Box::new(DiffuseLight::new(emit.build())),
+            MaterialSpec::DiffuseLight { emit, two_sided: false } =>
+                Box::new(DiffuseLight::new(emit.build()).one_sided()),
+            MaterialSpec::Emissive { material, emit, two_sided: true } =>
+                Box::new(Emissive::new(material.build(), emit.build())),
+            MaterialSpec::Emissive { material, emit, two_sided: false } =>
+                Box::new(Emissive::new(material.build(), emit.build()).one_sided()),
+            MaterialSpec::Isotropic(albedo) => Box::new(Isotropic::new(albedo.build()))
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PrincipledSpec {
+    base_color: TextureSpec,
+    metallic: TextureSpec,
+    roughness: TextureSpec,
+    specular: TextureSpec,
+    specular_tint: TextureSpec,
+    sheen: TextureSpec,
+    sheen_tint: TextureSpec,
+    clearcoat: TextureSpec,
+    clearcoat_roughness: TextureSpec,
+    transmission: TextureSpec,
+    index_refraction: f64,
+    emission: Option<TextureSpec>
+}
+
+impl PrincipledSpec {
+    fn build(&self) -> Principled {
+        Principled {
+            base_color: self.base_color.build(),
+            metallic: self.metallic.build(),
+            roughness: self.roughness.build(),
+            specular: self.specular.build(),
+            specular_tint: self.specular_tint.build(),
+            sheen: self.sheen.build(),
+            sheen_tint: self.sheen_tint.build(),
+            clearcoat: self.clearcoat.build(),
+            clearcoat_roughness: self.clearcoat_roughness.build(),
+            transmission: self.transmission.build(),
+            index_refraction: self.index_refraction,
+            emission: self.emission.as_ref().map(TextureSpec::build)
+        }
+    }
+}
+
+struct SceneParser<'a> {
+    base_dir: &'a Path,
+    width: usize,
+    height: Option<usize>,
+    aspect_ratio: f64,
+    spp: usize,
+    max_depth: usize,
+    background: Color3d,
+    tone_mapping: ToneMapping,
+    sampler: SamplerType,
+    seed: u64,
+    // Set from the command line, `image seed=` is ignored.
+    fixed_seed: bool,
+    environment: Option<Box<dyn Environment + Send + Sync>>,
+    camera: Option<CameraSpec>,
+    textures: HashMap<String, TextureSpec>,
+    materials: HashMap<String, MaterialSpec>,
+    world: HittableList,
+    lights: Vec<Box<dyn Light + Send + Sync>>
+}
+
+struct CameraSpec {
+    look_from: Point3d,
+    look_at: Point3d,
+    vup: Vec3d,
+    fov: f64,
+    aperture: f64,
+    focus_dist: f64,
+    shutter: (f64, f64)
+}
+
+impl<'a> SceneParser<'a> {
+    fn new(base_dir: &'a Path, seed: Option<u64>) -> Self {
+        seed_scene_random(seed.unwrap_or(0));
+        Self {
+            base_dir,
+            width: 400,
+            height: None,
+            aspect_ratio: 1.0,
+            spp: 100,
+            max_depth: 50,
+            background: Color3d::zero(),
+            tone_mapping: ToneMapping::default(),
+            sampler: SamplerType::Independent,
+            seed: seed.unwrap_or(0),
+            fixed_seed: seed.is_some(),
+            environment: None,
+            camera: None,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            world: HittableList::new(),
+            lights: vec![]
+        }
+    }
+
+    fn directive(&mut self, directive: &Directive) -> ParseResult<()> {
+        match directive.keyword {
+            "image" => self.image(directive),
+            "camera" => self.camera(directive),
+            "texture" => self.texture(directive),
+            "material" => self.material(directive),
+            "point_light" | "spot_light" | "directional_light" => self.light(directive),
+            "environment" | "sky" | "gradient" => self.environment(directive),
+            _ => {
+                let shape = self.shape(directive)?;
+                let shape = self.medium(directive, shape)?;
+                let shape = Self::transform(directive, shape)?;
+                self.world.add(shape);
+                Ok(())
+            }
+        }
+    }
+
+    fn image(&mut self, directive: &Directive) -> ParseResult<()> {
+        directive.positional(0)?;
+        self.width = directive.integer_or("width", self.width)?;
+        if directive.get("height").is_some() {
+            self.height = Some(directive.integer_or("height", 0)?);
+        }
+        self.aspect_ratio = directive.number_or("aspect_ratio", self.aspect_ratio)?;
+        self.spp = directive.integer_or("spp", self.spp)?;
+        self.max_depth = directive.integer_or("max_depth", self.max_depth)?;
+        self.background = directive.vector_or("background", self.background)?;
+        self.tone_mapping.exposure = directive.number_or("exposure", self.tone_mapping.exposure)?;
+        if directive.get("seed").is_some() {
+            let seed = directive.integer_or("seed", 0)? as u64;
+            if !self.fixed_seed {
+                // Restarts the random numbers of everything built after this directive.
+                self.seed = seed;
+                seed_scene_random(seed);
+            }
+        }
+        if let Some(name) = directive.get("sampler") {
+            self.sampler = match SamplerType::parse(name) {
+                Some(sampler) => sampler,
+                None => return directive.error(format!("unknown sampler `{}`", name))
+            };
+        }
+        if let Some(name) = directive.get("tone_map") {
+            self.tone_mapping.operator = match ToneOperator::parse(name, DEFAULT_WHITE) {
+                Some(operator) => operator,
+                None => return directive.error(format!("unknown tone mapping `{}`", name))
+            };
+        }
+        if directive.get("white").is_some() {
+            let white = directive.number("white")?;
+            if white <= 0.0 {
+                return directive.error("`white` must be positive".to_string());
+            }
+            self.tone_mapping.operator = match self.tone_mapping.operator.with_white(white) {
+                Some(operator) => operator,
+                None => return directive.error("`white` only applies to the extended_reinhard tone mapping".to_string())
+            };
+        }
+
+        if self.aspect_ratio <= 0.0 {
+            return directive.error("`aspect_ratio` must be positive".to_string());
+        }
+        let height = self.image_height();
+        if self.width < 2 || height < 2 {
+            return directive.error("image must be at least 2 pixels wide and high".to_string());
+        }
+        if self.width > MAX_IMAGE_SIZE || height > MAX_IMAGE_SIZE {
+            return directive.error(format!("image can be at most {} pixels wide and high", MAX_IMAGE_SIZE));
+        }
+        if self.width * height > MAX_IMAGE_PIXELS {
+            return directive.error(format!("image can have at most {} pixels", MAX_IMAGE_PIXELS));
+        }
+        if self.spp == 0 {
+            return directive.error("`spp` must be positive".to_string());
+        }
+        if self.max_depth == 0 {
+            return directive.error("`max_depth` must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    fn image_height(&self) -> usize {
+        self.height.unwrap_or((self.width as f64 / self.aspect_ratio) as usize)
+    }
+
+    fn camera(&mut self, directive: &Directive) -> ParseResult<()> {
+        directive.positional(0)?;
+        let look_from = directive.vector("look_from")?;
+        let look_at = directive.vector_or("look_at", Point3d::zero())?;
+        if (look_from - look_at).near_zero() {
+            return directive.error("`look_from` and `look_at` must differ".to_string());
+        }
+        let vup = directive.vector_or("vup", Vec3d::new(0.0, 1.0, 0.0))?;
+        if vup.near_zero() || vup.normalized().cross(&(look_from - look_at).normalized()).near_zero() {
+            return directive.error("`vup` must not be parallel to the view direction".to_string());
+        }
+        let fov = directive.number_or("fov", 40.0)?;
+        if fov <= 0.0 || fov >= 180.0 {
+            return directive.error("`fov` must be between 0 and 180 degrees".to_string());
+        }
+        let camera = CameraSpec {
+            look_from,
+            look_at,
+            vup,
+            fov,
+            aperture: directive.number_or("aperture", 0.0)?,
+            focus_dist: directive.number_or("focus_dist", (look_from - look_at).norm())?,
+            shutter: directive.pair_or("shutter", (0.0, 0.0))?
+        };
+        self.camera = Some(camera);
+        Ok(())
+    }
+
+    fn light(&mut self, directive: &Directive) -> ParseResult<()> {
+        directive.positional(0)?;
+        let light: Box<dyn Light + Send + Sync> = match directive.keyword {
+            "point_light" => Box::new(PointLight::new(
+                directive.vector("position")?, directive.vector("intensity")?
+            )),
+            "spot_light" => {
+                let position = directive.vector("position")?;
+                let look_at = directive.vector("look_at")?;
+                if (look_at - position).near_zero() {
+                    return directive.error("`position` and `look_at` must differ".to_string());
+                }
+                let angle = directive.number_or("angle", 30.0)?;
+                Box::new(SpotLight::new(
+                    position, look_at, directive.vector("intensity")?,
+                    Angle::DegAngle(angle), Angle::DegAngle(directive.number_or("falloff", angle)?)
+                ))
+            }
+            _ => {
+                let direction = directive.vector("direction")?;
+                if direction.near_zero() {
+                    return directive.error("`direction` must not be zero".to_string());
+                }
+                Box::new(DirectionalLight::new(direction, directive.vector("irradiance")?))
+            }
+        };
+
+        self.lights.push(light);
+        Ok(())
+    }
+
+    // Replaces the background color.
+    fn environment(&mut self, directive: &Directive) -> ParseResult<()> {
+        directive.positional(0)?;
+        let environment: Box<dyn Environment + Send + Sync> = match directive.keyword {
+            "environment" => {
+                let file = self.base_dir.join(directive.require("file")?);
+                let rotation = Angle::DegAngle(directive.number_or("rotation", 0.0)?);
+                let intensity = directive.number_or("intensity", 1.0)?;
+                Box::new(EnvironmentMap::load(&file, rotation, intensity)
+                    .or_else(|e| directive.error(format!("cannot load `{}`: {}", file.display(), e)))?)
+            }
+            "sky" => {
+                let sun_direction = directive.vector("sun_direction")?;
+                if sun_direction.near_zero() {
+                    return directive.error("`sun_direction` must not be zero".to_string());
+                }
+                let mut sky = PreethamSky::new(sun_direction, directive.number_or("turbidity", 3.0)?)
+                    .with_sun(
+                        directive.vector_or("sun", Color3d::zero())?,
+                        Angle::DegAngle(directive.number_or("sun_radius", 0.27)?)
+                    );
+                sky.intensity = directive.number_or("intensity", 1.0)?;
+                Box::new(sky)
+            }
+            _ => {
+                let default = GradientSky::default();
+                Box::new(GradientSky::new(
+                    directive.vector_or("bottom", default.bottom)?,
+                    directive.vector_or("top", default.top)?
+                ))
+            }
+        };
+
+        self.environment = Some(environment);
+        Ok(())
+    }
+
+    fn texture_value(&self, directive: &Directive, key: &str) -> ParseResult<TextureSpec> {
+        let value = directive.require(key)?;
+        if let Some(texture) = self.textures.get(value) {
+            Ok(texture.clone())
+        } else if value.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+            Ok(TextureSpec::Solid(directive.parse_vector(key, value)?))
+        } else {
+            directive.error(format!("undefined texture `{}`", value))
+        }
+    }
+
+    fn texture_value_or(&self, directive: &Directive, key: &str, default: f64) -> ParseResult<TextureSpec> {
+        match directive.get(key) {
+            Some(_) => self.texture_value(directive, key),
+            None => Ok(TextureSpec::Solid(Color3d::only(default)))
+        }
+    }
+
+    fn texture(&mut self, directive: &Directive) -> ParseResult<()> {
+        let (name, kind) = match directive.positional(2)? {
+            &[name, kind] => (name, kind),
+            _ => unreachable!()
+        };
+        let texture = match kind {
+            "solid" => TextureSpec::Solid(directive.vector("color")?),
+            "checker" => TextureSpec::Checker(
+                Box::new(self.texture_value(directive, "even")?),
+                Box::new(self.texture_value(directive, "odd")?)
+            ),
+            "noise" => TextureSpec::Noise(NoiseTexture::new(directive.number("scale")?)),
+            "image" => {
+                let file = self.base_dir.join(directive.require("file")?);
+                let image = ImageTexture::from_file(file.to_string_lossy().to_string())
+                    .or_else(|e| directive.error(format!("cannot load `{}`: {}", file.display(), e)))?;
+                TextureSpec::Image(image)
+            }
+            _ => return directive.error(format!("unknown texture type `{}`", kind))
+        };
+        self.textures.insert(name.to_string(), texture);
+        Ok(())
+    }
+
+    fn material(&mut self, directive: &Directive) -> ParseResult<()> {
+        let (name, kind) = match directive.positional(2)? {
+            &[name, kind] => (name, kind),
+            _ => unreachable!()
+        };
+        let material = match kind {
+            "diffuse" => MaterialSpec::Diffuse(self.texture_value(directive, "albedo")?),
+            "metal" => MaterialSpec::Metal {
+                albedo: directive.vector("albedo")?,
+                fuzz: directive.number_or("fuzz", 0.0)?
+            },
+            "conductor" => {
+                let (roughness_u, roughness_v) = directive.roughness_or("roughness", 0.0)?;
+                let conductor = match directive.get("metal") {
+                    Some(metal) => Conductor::preset(metal, roughness_u, roughness_v).map_or_else(|| {
+                        let names: Vec<_> = CONDUCTOR_PRESETS.iter().map(|(name, _, _)| *name).collect();
+                        directive.error(format!("unknown metal `{}`, expected one of {}", metal, names.join(", ")))
+                    }, Ok)?,
+                    None => Conductor::new(
+                        directive.vector("eta")?, directive.vector("k")?, roughness_u, roughness_v
+                    )
+                };
+                MaterialSpec::Conductor(conductor)
+            }
+            "dielectric" => MaterialSpec::Dielectric(
+                Dielectric::new(directive.number_or("ior", 1.5)?)
+                    .with_absorption(directive.vector_or("absorption", Color3d::zero())?)
+            ),
+            "rough_dielectric" => {
+                let (roughness_u, roughness_v) = directive.roughness_or("roughness", 0.0)?;
+                MaterialSpec::RoughDielectric(
+                    RoughDielectric::new(directive.number_or("ior", 1.5)?, roughness_u, roughness_v)
+                        .with_absorption(directive.vector_or("absorption", Color3d::zero())?)
+                )
+            }
+            "principled" => MaterialSpec::Principled(Box::new(PrincipledSpec {
+                base_color: self.texture_value_or(directive, "base_color", 0.8)?,
+                metallic: self.texture_value_or(directive, "metallic", 0.0)?,
+                roughness: self.texture_value_or(directive, "roughness", 0.5)?,
+                specular: self.texture_value_or(directive, "specular", 0.5)?,
+                specular_tint: self.texture_value_or(directive, "specular_tint", 0.0)?,
+                sheen: self.texture_value_or(directive, "sheen", 0.0)?,
+                sheen_tint: self.texture_value_or(directive, "sheen_tint", 0.5)?,
+                clearcoat: self.texture_value_or(directive, "clearcoat", 0.0)?,
+                clearcoat_roughness: self.texture_value_or(directive, "clearcoat_roughness", 0.1)?,
+                transmission: self.texture_value_or(directive, "transmission", 0.0)?,
+                index_refraction: directive.number_or("ior", 1.5)?,
+                emission: match directive.get("emission") {
+                    Some(_) => Some(self.texture_value(directive, "emission")?),
+                    None => None
+                }
+            })),
+            "mix" => MaterialSpec::Mix {
+                a: Box::new(self.material_value(directive, "a")?.clone()),
+                b: Box::new(self.material_value(directive, "b")?.clone()),
+                mask: self.texture_value(directive, "mask")?
+            },
+            "coated" => MaterialSpec::Coated {
+                base: Box::new(self.material_value(directive, "base")?.clone()),
+                index_refraction: directive.number_or("ior", 1.5)?,
+                roughness: directive.roughness_or("roughness", 0.0)?
+            },
+            "normal_map" => MaterialSpec::NormalMap {
+                base: Box::new(self.material_value(directive, "base")?.clone()),
+                map: self.texture_value(directive, "map")?
+            },
+            "bump_map" => MaterialSpec::BumpMap {
+                base: Box::new(self.material_value(directive, "base")?.clone()),
+                height: self.texture_value(directive, "height")?,
+                scale: directive.number_or("scale", 1.0)?
+            },
+            "diffuse_light" => MaterialSpec::DiffuseLight {
+                emit: self.texture_value(directive, "emit")?,
+                two_sided: !directive.boolean_or("one_sided", false)?
+            },
+            "isotropic" => MaterialSpec::Isotropic(self.texture_value(directive, "albedo")?),
+            _ => return directive.error(format!("unknown material type `{}`", kind))
+        };
+        // Any other material glows as well when given an emission.
+        let material = match directive.get("emit") {
+            Some(_) if kind != "diffuse_light" => MaterialSpec::Emissive {
+                material: Box::new(material),
+                emit: self.texture_value(directive, "emit")?,
+                two_sided: !directive.boolean_or("one_sided", false)?
+            },
+            _ => material
+        };
+        self.materials.insert(name.to_string(), material);
+        Ok(())
+    }
+
+    // `None` for the boundary of a medium, which is never shaded.
+    fn shape_material(&self, directive: &Directive) -> ParseResult<Option<&MaterialSpec>> {
+        if directive.get("density").is_some() {
+            return Ok(None);
+        }
+        self.material_value(directive, "material").map(Some)
+    }
+
+    fn material_value(&self, directive: &Directive, key: &str) -> ParseResult<&MaterialSpec> {
+        let name = directive.require(key)?;
+        match self.materials.get(name) {
+            Some(material) => Ok(material),
+            None => directive.error(format!("undefined material `{}`", name))
+        }
+    }
+
+    fn build_material(material: Option<&MaterialSpec>) -> Box<dyn Material + Send + Sync> {
+        material.map_or_else(|| Box::new(DummyMaterial) as Box<dyn Material + Send + Sync>, MaterialSpec::build)
+    }
+
+    fn shape(&self, directive: &Directive) -> ParseResult<Box<dyn Hittable + Send + Sync>> {
+        directive.positional(0)?;
+        let material = || self.shape_material(directive).map(Self::build_material);
+        let shape: Box<dyn Hittable + Send + Sync> = match directive.keyword {
+            "sphere" => Box::new(Sphere::new(
+                directive.vector("center")?, directive.positive_number("radius")?, material()?
+            )),
+            "moving_sphere" => Box::new(MovingSphere::new(
+                directive.vector("center0")?, directive.vector("center1")?,
+                directive.number_or("time0", 0.0)?, directive.number_or("time1", 1.0)?,
+                directive.positive_number("radius")?, material()?
+            )),
+            "xy_rect" => Box::new(XYRect::new(
+                directive.pair("from")?, directive.pair("to")?, directive.number("k")?, material()?
+            )),
+            "xz_rect" => Box::new(XZRect::new(
+                directive.pair("from")?, directive.pair("to")?, directive.number("k")?, material()?
+            )),
+            "yz_rect" => Box::new(YZRect::new(
+                directive.pair("from")?, directive.pair("to")?, directive.number("k")?, material()?
+            )),
+            "triangle" => Box::new(Triangle::new(
+                directive.vector("p0")?, directive.vector("p1")?, directive.vector("p2")?, material()?
+            )),
+            "box" => Box::new(RectBox::new(
+                directive.vector("min")?, directive.vector("max")?, material()?
+            )),
+            "obj" => {
+                let file = self.base_dir.join(directive.require("file")?);
+                let mut model = ObjModel::load(&file).or_else(|e| directive.error(e.to_string()))?;
+                model.scale(directive.number_or("scale", 1.0)?);
+                if directive.get("material").is_some() || directive.get("density").is_some() {
+                    let material = self.shape_material(directive)?;
+                    model.set_material(|| Self::build_material(material));
+                }
+                Box::new(model.into_bvh(0.0, 1.0))
+            }
+            keyword => return directive.error(format!("unknown directive `{}`", keyword))
+        };
+
+        Ok(shape)
+    }
+
+    fn medium(&self, directive: &Directive, shape: Box<dyn Hittable + Send + Sync>)
+        -> ParseResult<Box<dyn Hittable + Send + Sync>> {
+        match directive.get("density") {
+            Some(density) => {
+                let density = directive.parse_number("density", density)?;
+                let albedo = self.texture_value(directive, "albedo")?;
+                Ok(Box::new(ConstantMedium::new(shape, density, albedo.build())))
+            }
+            None => Ok(shape)
+        }
+    }
+
+    fn transform(directive: &Directive, mut shape: Box<dyn Hittable + Send + Sync>)
+        -> ParseResult<Box<dyn Hittable + Send + Sync>> {
+        for &(key, value) in &directive.arguments {
+            shape = match key {
+                "translate" => Box::new(shape.translate(directive.parse_vector(key, value)?)),
+                "rotate_x" => Box::new(shape.rotate_x(Angle::DegAngle(directive.parse_number(key, value)?))),
+                "rotate_y" => Box::new(shape.rotate_y(Angle::DegAngle(directive.parse_number(key, value)?))),
+                "rotate_z" => Box::new(shape.rotate_z(Angle::DegAngle(directive.parse_number(key, value)?))),
+                "flip_face" if directive.parse_boolean(key, value)? => Box::new(shape.flip_face()),
+                "flip_face" => shape,
+                _ => continue
+            };
+            directive.used.borrow_mut().insert(key);
+        }
+
+        Ok(shape)
+    }
+
+    fn finish(self, last_line: usize) -> ParseResult<Scene> {
+        let height = self.image_height();
+        let camera = match self.camera {
+            Some(camera) => camera,
+            None => return Err(syntax_error(last_line, "missing `camera` directive".to_string()))
+        };
+        if self.world.objects.is_empty() {
+            return Err(syntax_error(last_line, "the scene has no shapes".to_string()));
+        }
+        let camera = Camera::new_with_shutter(
+            camera.look_from,
+            camera.look_at,
+            camera.vup,
+            self.width as f64 / height as f64,
+            Angle::DegAngle(camera.fov),
+            camera.aperture,
+            camera.focus_dist,
+            camera.shutter.0, camera.shutter.1
+        );
+
+        let mut scene = Scene::new(height, self.width, self.world, camera, self.spp, self.background);
+        scene.max_depth = self.max_depth;
+        scene.tone_mapping = self.tone_mapping;
+        scene.sampler = self.sampler;
+        scene.seed = self.seed;
+        scene.lights = self.lights;
+        if let Some(environment) = self.environment {
+            scene.background = environment;
+        }
+
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = "
+        # A sphere on a checkered floor.
+        image width=40 aspect_ratio=2.0 spp=8 max_depth=6 seed=3
+        camera look_from=0,1,-5 look_at=0,1,0 fov=35
+        texture checker checker even=0.2 odd=0.9
+        material ground diffuse albedo=checker
+        material glass dielectric ior=1.5
+        sphere center=0,-1000,0 radius=1000 material=ground
+        sphere center=0,1,0 radius=1 material=glass rotate_y=30
+        point_light position=0,5,0 intensity=20
+    ";
+
+    // A scene with `line` as its camera directive, on line 4.
+    fn with_camera(line: &str) -> String {
+        format!("image width=20 max_depth=4\nmaterial white diffuse albedo=0.7\n\n{}\n{}", line, SPHERE)
+    }
+
+    const SPHERE: &str = "sphere center=0,0,0 radius=1 material=white";
+
+    fn error_line(source: &str) -> (usize, String) {
+        match parse_scene(source, Path::new(""), None) {
+            Err(SceneFileError::Syntax { line, message }) => (line, message),
+            Err(error) => panic!("unexpected error {}", error),
+            Ok(_) => panic!("no error for {:?}", source)
+        }
+    }
+
+    #[test]
+    fn parses_a_valid_scene() {
+        let scene = parse_scene(VALID, Path::new(""), None).unwrap();
+        assert_eq!((scene.width, scene.height), (40, 20));
+        assert_eq!((scene.spp, scene.max_depth, scene.seed), (8, 6, 3));
+        assert_eq!(scene.world.objects.len(), 2);
+        assert_eq!(scene.lights.len(), 1);
+    }
+
+    #[test]
+    fn reports_the_line_of_a_malformed_directive() {
+        let cases = [
+            ("camera look_from=0,0,-5 fov=0", "`fov` must be between 0 and 180 degrees"),
+            ("camera look_from=0,0,-5 fov=180", "`fov` must be between 0 and 180 degrees"),
+            ("camera look_from=0,0,-5 fov=-30", "`fov` must be between 0 and 180 degrees"),
+            ("camera look_from=1,2,3 look_at=1,2,3", "`look_from` and `look_at` must differ"),
+            ("camera look_from=0,5,0 vup=0,2,0", "`vup` must not be parallel to the view direction"),
+            ("camera look_from=0,0,-5 vup=0,0,0", "`vup` must not be parallel to the view direction"),
+            ("camera look_from=0,0,-5 fov=wide", "`fov` expects a number, found `wide`"),
+            ("camera look_from=0,0,-5 zoom=2", "unknown argument `zoom` for `camera`")
+        ];
+        for (line, message) in cases.iter() {
+            let (error_line, error) = error_line(&with_camera(line));
+            assert_eq!((error_line, error.as_str()), (4, *message), "{}", line);
+        }
+    }
+
+    #[test]
+    fn reports_the_line_of_an_invalid_shape_or_image() {
+        let source = "
+            camera look_from=0,0,-5
+            material white diffuse albedo=0.7
+            sphere center=0,0,0 radius=1 material=white
+            sphere center=0,0,0 radius=0 material=white
+        ";
+        assert_eq!(error_line(source), (5, "`radius` must be positive".to_string()));
+
+        let source = "
+            camera look_from=0,0,-5
+            material white diffuse albedo=0.7
+            moving_sphere center0=0,0,0 center1=1,0,0 radius=-1 material=white
+        ";
+        assert_eq!(error_line(source), (4, "`radius` must be positive".to_string()));
+
+        let source = "# Nothing to bounce.\n\nimage max_depth=0\ncamera look_from=0,0,-5";
+        assert_eq!(error_line(source), (3, "`max_depth` must be positive".to_string()));
+
+        let source = "camera look_from=0,0,-5\n\n";
+        assert_eq!(error_line(source), (2, "the scene has no shapes".to_string()));
+    }
+}
@@ -6,6 +6,7 @@ use std::borrow::Borrow;
 use crate::acceleration::aabb::AABB;
 use std::ops::Neg;
 use std::f64::consts::{PI, TAU};
+use crate::vec3d_extensions::{OrthonormalBasis, uniform_cone_pdf, uniform_sphere_pdf};
 
 #[derive(Clone)]
 pub struct Sphere<M>
@@ -33,6 +34,19 @@ impl<M: Material + Send + Sync> Sphere<M> {
     property! { radius: f64 }
 }
 
+// Derivatives of the point along u and v at the point `p` of the unit sphere, the frame of the
+// hit record is kept at the poles.
+fn with_sphere_tangents<'a>(record: HitRecord<'a>, p: &Point3d, radius: f64) -> HitRecord<'a> {
+    let sin_theta = (p.x * p.x + p.z * p.z).sqrt();
+    if sin_theta < 1e-8 {
+        return record
+    }
+
+    let dpdu = Vec3d::new(p.z, 0.0, -p.x) * (TAU * radius);
+    let dpdv = Vec3d::new(-p.x * p.y / sin_theta, sin_theta, -p.y * p.z / sin_theta) * (PI * radius);
+    record.with_tangents(dpdu, dpdv)
+}
+
 fn solve_sphere_equation(ray: &Ray, center: Point3d, radius: f64, t_min: f64, t_max: f64)
     -> Option<(f64, Point3d, Vec3d)> {
     let oc = ray.origin() - center;
@@ -59,23 +73,57 @@ fn solve_sphere_equation(ray: &Ray, center: Point3d, radius: f64, t_min: f64, t_
 }
 
 impl<M: Material + Sync + Send> Hittable for Sphere<M> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         solve_sphere_equation(ray, self.center, self.radius, t_min, t_max)
             .map(|(root, point, outward_normal)| {
                 // Outward normal is actually a point on the unit sphere centered at the origin.
                 let (u, v) = Self::get_sphere_uv(&outward_normal);
-                HitRecord::new_with_face_normal(
+                let record = HitRecord::new_with_face_normal(
                     root, point, u, v, outward_normal, self.material.borrow(), ray
-                )
+                );
+                with_sphere_tangents(record, &outward_normal, self.radius)
             })
     }
 
-    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
         Some(AABB::new(
             self.center - Point3d::only(self.radius),
             self.center + Point3d::only(self.radius)
         ))
     }
+
+    fn is_emitter(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: Point3d, direction: Vec3d) -> f64 {
+        if self.hit(&Ray::new(origin, direction), 0.001, f64::INFINITY).is_none() {
+            return 0.0
+        }
+
+        let distance_squared = (self.center - origin).norm_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // Every direction hits the sphere from inside.
+            return uniform_sphere_pdf()
+        }
+
+        uniform_cone_pdf((1.0 - radius_squared / distance_squared).sqrt())
+    }
+
+    fn random(&self, origin: Point3d) -> Vec3d {
+        let direction = self.center - origin;
+        let distance_squared = direction.norm_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return Vec3d::random_unit_vector()
+        }
+
+        // Uniform direction in the cone of directions subtended by the sphere.
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        OrthonormalBasis::from_w(direction / distance_squared.sqrt())
+            .local(Vec3d::random_in_cone(cos_theta_max))
+    }
 }
 
 pub struct MovingSphere<M: Material + Send + Sync> {
@@ -105,13 +153,14 @@ impl<M: Material + Send + Sync> MovingSphere<M> {
 }
 
 impl<M: Material + Send + Sync> Hittable for MovingSphere<M> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         solve_sphere_equation(ray, self.center(ray.time()), self.radius, t_min, t_max)
             .map(|(root, point, outward_normal)| {
                 let (u, v) = Sphere::<M>::get_sphere_uv(&outward_normal);
-                HitRecord::new_with_face_normal(
+                let record = HitRecord::new_with_face_normal(
                     root, point, u, v,outward_normal, self.material.borrow(), ray
-                )
+                );
+                with_sphere_tangents(record, &outward_normal, self.radius)
             })
     }
 
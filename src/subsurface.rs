@@ -36,7 +36,7 @@ impl<H, M> Hittable for ConstantMedium<H, M>
 where
     H: Hittable + Send + Sync,
     M: Material + Send + Sync {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut record1 =
             self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY)?;
         let mut record2 =
@@ -70,7 +70,7 @@ where
         Some(HitRecord::new_with_face_normal(t,
                                              point,
                                              record1.u, record1.v,
-                                             record1.normal,
+                                             record1.outward_normal(),
                                              &self.phase_function, ray))
     }
 
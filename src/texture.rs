@@ -38,6 +38,19 @@ impl Texture for SolidColor {
     }
 }
 
+// Constant colors and gray values can be used wherever a texture is expected.
+impl Texture for Color3d {
+    fn eval(&self, _u: f64, _v: f64, _p: Point3d) -> Color3d {
+        *self
+    }
+}
+
+impl Texture for f64 {
+    fn eval(&self, _u: f64, _v: f64, _p: Point3d) -> Color3d {
+        Color3d::only(*self)
+    }
+}
+
 pub struct CheckerTexture<T1, T2>
 where T1: Texture, T2: Texture {
     pub even: T1,
@@ -0,0 +1,171 @@
+use crate::color::Color3d;
+use crate::util::clamp;
+
+// Maps the linear radiance of a pixel to the [0, 1] range of a display before the sRGB curve.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneOperator {
+    // Cuts off everything above 1.
+    Clamp,
+    // x / (1 + x) per channel, nothing reaches white.
+    Reinhard,
+    // Reinhard with the value `white` mapped to 1.
+    ExtendedReinhard { white: f64 },
+    // The filmic curve of the ACES reference and output transforms, after Stephen Hill's fit.
+    Aces,
+    // Troy Sobotka's AgX with the polynomial fit of its default contrast look, desaturates
+    // highlights instead of skewing their hue.
+    Agx
+}
+
+// Luminance mapped to white by extended Reinhard unless another one is given.
+pub const DEFAULT_WHITE: f64 = 4.0;
+
+pub const TONE_OPERATOR_NAMES: [&str; 5] = ["clamp", "reinhard", "extended_reinhard", "aces", "agx"];
+
+impl ToneOperator {
+    pub fn parse(name: &str, white: f64) -> Option<Self> {
+        match name {
+            "clamp" => Some(ToneOperator::Clamp),
+            "reinhard" => Some(ToneOperator::Reinhard),
+            "extended_reinhard" => Some(ToneOperator::ExtendedReinhard { white }),
+            "aces" => Some(ToneOperator::Aces),
+            "agx" => Some(ToneOperator::Agx),
+            _ => None
+        }
+    }
+
+    // The same operator mapping `white` to 1, None for operators without a white point.
+    pub fn with_white(self, white: f64) -> Option<Self> {
+        match self {
+            ToneOperator::ExtendedReinhard { .. } => Some(ToneOperator::ExtendedReinhard { white }),
+            _ => None
+        }
+    }
+
+    pub fn white(&self) -> Option<f64> {
+        match *self {
+            ToneOperator::ExtendedReinhard { white } => Some(white),
+            _ => None
+        }
+    }
+
+    pub fn apply(&self, color: Color3d) -> Color3d {
+        match *self {
+            ToneOperator::Clamp => color,
+            ToneOperator::Reinhard => map_channels(color, |x| x / (1.0 + x)),
+            ToneOperator::ExtendedReinhard { white } =>
+                map_channels(color, |x| x * (1.0 + x / (white * white)) / (1.0 + x)),
+            ToneOperator::Aces => aces(color),
+            ToneOperator::Agx => agx(color)
+        }
+    }
+}
+
+// Exposure is in stops, every step doubles the brightness.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMapping {
+    pub exposure: f64,
+    pub operator: ToneOperator
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self { exposure: 0.0, operator: ToneOperator::Clamp }
+    }
+}
+
+impl ToneMapping {
+    // Linear color scaled by the exposure, for formats that keep the full range.
+    pub fn expose(&self, color: Color3d) -> Color3d {
+        color * 2f64.powf(self.exposure)
+    }
+
+    // 8-bit sRGB of a linear color.
+    pub fn display_color(&self, color: Color3d) -> [u8; 3] {
+        let exposed = map_channels(self.expose(color), |x| x.max(0.0));
+        let mapped = self.operator.apply(exposed);
+        let quantize = |x: f64| (srgb_encode(clamp(x, 0.0, 1.0)) * 255.0).round() as u8;
+        [quantize(mapped.x), quantize(mapped.y), quantize(mapped.z)]
+    }
+}
+
+// The sRGB transfer function, linear near black and a 2.4 power elsewhere.
+pub fn srgb_encode(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// The inverse of `srgb_encode`.
+pub fn srgb_decode(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[inline]
+fn map_channels(color: Color3d, f: impl Fn(f64) -> f64) -> Color3d {
+    Color3d::new(f(color.x), f(color.y), f(color.z))
+}
+
+// Row-major 3x3 matrix times a color.
+#[inline]
+fn transform(m: &[[f64; 3]; 3], c: Color3d) -> Color3d {
+    Color3d::new(
+        m[0][0] * c.x + m[0][1] * c.y + m[0][2] * c.z,
+        m[1][0] * c.x + m[1][1] * c.y + m[1][2] * c.z,
+        m[2][0] * c.x + m[2][1] * c.y + m[2][2] * c.z,
+    )
+}
+
+// sRGB to the ACES working space with the exposure of the reference transform.
+const ACES_INPUT: [[f64; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777]
+];
+
+const ACES_OUTPUT: [[f64; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602]
+];
+
+fn aces(color: Color3d) -> Color3d {
+    let fitted = map_channels(transform(&ACES_INPUT, color), |x| {
+        (x * (x + 0.0245786) - 0.000090537) / (x * (0.983729 * x + 0.4329510) + 0.238081)
+    });
+    transform(&ACES_OUTPUT, fitted)
+}
+
+// sRGB to the rotated and inset primaries of AgX and back.
+const AGX_INSET: [[f64; 3]; 3] = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104]
+];
+
+const AGX_OUTSET: [[f64; 3]; 3] = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116]
+];
+
+// Range of the log encoding in stops around middle gray.
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+fn agx(color: Color3d) -> Color3d {
+    let encoded = map_channels(transform(&AGX_INSET, color), |x| {
+        let ev = clamp(x.max(1e-10).log2(), AGX_MIN_EV, AGX_MAX_EV);
+        let x = (ev - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+    // The curve produces display values with a 2.2 gamma, made linear again for the sRGB curve.
+    map_channels(transform(&AGX_OUTSET, encoded), |x| x.max(0.0).powf(2.2))
+}
@@ -21,14 +21,14 @@ impl<T: Hittable + Send + Sync> Translate<T> {
 }
 
 impl<T: Hittable + Send + Sync> Hittable for Translate<T> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let moved_ray = Ray::new_with_time(ray.origin() - self.offset, ray.direction(), ray.time());
         self.hittable.hit(&moved_ray, t_min, t_max).map(|record| {
             HitRecord::new_with_face_normal(
                 record.t, record.point + self.offset,
-                record.u, record.v, record.normal, record.material,
+                record.u, record.v, record.outward_normal(), record.material,
                 &moved_ray
-            )
+            ).with_tangents(record.dpdu, record.dpdv)
         })
     }
 
@@ -40,6 +40,60 @@ impl<T: Hittable + Send + Sync> Hittable for Translate<T> {
             record
         })
     }
+
+    fn is_emitter(&self) -> bool {
+        self.hittable.is_emitter()
+    }
+
+    fn pdf_value(&self, origin: Point3d, direction: Vec3d) -> f64 {
+        self.hittable.pdf_value(origin - self.offset, direction)
+    }
+
+    fn random(&self, origin: Point3d) -> Vec3d {
+        self.hittable.random(origin - self.offset)
+    }
+}
+
+// Turns a surface around, what was its back face is hit as the front face. Used for lights that
+// only emit from the front.
+pub struct FlipFace<T>
+where
+    T: Hittable + Send + Sync {
+    hittable: T
+}
+
+impl<T: Hittable + Send + Sync> FlipFace<T> {
+    pub fn new(hittable: T) -> Self {
+        Self { hittable }
+    }
+}
+
+impl<T: Hittable + Send + Sync> Hittable for FlipFace<T> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.hittable.hit(ray, t_min, t_max).map(|record| {
+            HitRecord::new_with_face_normal(
+                record.t, record.point,
+                record.u, record.v, -record.outward_normal(), record.material,
+                ray
+            ).with_tangents(record.dpdu, record.dpdv)
+        })
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.hittable.bounding_box(time0, time1)
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.hittable.is_emitter()
+    }
+
+    fn pdf_value(&self, origin: Point3d, direction: Vec3d) -> f64 {
+        self.hittable.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3d) -> Vec3d {
+        self.hittable.random(origin)
+    }
 }
 
 pub trait Transformable
@@ -61,6 +115,10 @@ where
     fn rotate_z(self, angle: Angle) -> RotateZ<Self> {
         RotateZ::new(self, angle)
     }
+
+    fn flip_face(self) -> FlipFace<Self> {
+        FlipFace::new(self)
+    }
 }
 
 impl<T: Hittable + Send + Sync + Sized> Transformable for T {}
@@ -141,7 +199,7 @@ macro_rules! impl_rotation {
         impl<T> Hittable for $rotation_name<T>
         where
             T: Hittable + Send + Sync {
-            fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+            fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
                 let origin = self.rotate(ray.origin());
                 let direction = self.rotate(ray.direction());
 
@@ -152,16 +210,28 @@ macro_rules! impl_rotation {
                         record.t,
                         self.reverse_rotate(record.point),
                         record.u, record.v,
-                        self.reverse_rotate(record.normal),
+                        self.reverse_rotate(record.outward_normal()),
                         record.material,
                         &rotated_ray
-                    )
+                    ).with_tangents(self.reverse_rotate(record.dpdu), self.reverse_rotate(record.dpdv))
                 })
             }
 
-            fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+            fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
                 self.bounds.clone()
             }
+
+            fn is_emitter(&self) -> bool {
+                self.hittable.is_emitter()
+            }
+
+            fn pdf_value(&self, origin: Point3d, direction: Vec3d) -> f64 {
+                self.hittable.pdf_value(self.rotate(origin), self.rotate(direction))
+            }
+
+            fn random(&self, origin: Point3d) -> Vec3d {
+                self.reverse_rotate(self.hittable.random(self.rotate(origin)))
+            }
         }
     };
 }
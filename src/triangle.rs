@@ -0,0 +1,240 @@
+use std::borrow::Borrow;
+use std::sync::Arc;
+
+use crate::vec3::{Point3d, Vec3d};
+use crate::hittable::{Hittable, HitRecord, area_pdf_to_solid_angle};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::acceleration::aabb::AABB;
+use crate::util::random_2d;
+
+type Point2d = (f64, f64);
+
+// Möller–Trumbore intersection, returns the distance and the barycentric coordinates of p1 and p2.
+fn intersect_triangle(ray: &Ray, p0: Point3d, p1: Point3d, p2: Point3d, t_min: f64, t_max: f64)
+    -> Option<(f64, f64, f64)> {
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let p = ray.direction().cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < 1e-12 {
+        return None
+    }
+
+    let inv_determinant = 1.0 / determinant;
+    let s = ray.origin() - p0;
+    let b1 = s.dot(&p) * inv_determinant;
+    if !(0.0..=1.0).contains(&b1) {
+        return None
+    }
+
+    let q = s.cross(&edge1);
+    let b2 = ray.direction().dot(&q) * inv_determinant;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None
+    }
+
+    let t = edge2.dot(&q) * inv_determinant;
+    if t < t_min || t > t_max {
+        return None
+    }
+
+    Some((t, b1, b2))
+}
+
+fn triangle_bounds(p0: Point3d, p1: Point3d, p2: Point3d) -> AABB {
+    // Pad the box so triangles parallel to an axis plane do not have a degenerate box.
+    let padding = Vec3d::only(0.0001);
+    AABB::new(
+        Vec3d::element_wise_min(p0, Vec3d::element_wise_min(p1, p2)) - padding,
+        Vec3d::element_wise_max(p0, Vec3d::element_wise_max(p1, p2)) + padding
+    )
+}
+
+// Derivatives of the point with respect to the texture coordinates, None for degenerate ones.
+fn triangle_tangents(p: [Point3d; 3], uv: [Point2d; 3]) -> Option<(Vec3d, Vec3d)> {
+    let (du02, dv02) = (uv[0].0 - uv[2].0, uv[0].1 - uv[2].1);
+    let (du12, dv12) = (uv[1].0 - uv[2].0, uv[1].1 - uv[2].1);
+    let determinant = du02 * dv12 - dv02 * du12;
+    if determinant.abs() < 1e-12 {
+        return None
+    }
+
+    let (dp02, dp12) = (p[0] - p[2], p[1] - p[2]);
+    let inv_determinant = 1.0 / determinant;
+    Some((
+        (dv12 * dp02 - dv02 * dp12) * inv_determinant,
+        (du02 * dp12 - du12 * dp02) * inv_determinant
+    ))
+}
+
+#[inline]
+fn interpolate<T>(values: [T; 3], b1: f64, b2: f64) -> T
+where T: std::ops::Mul<f64, Output = T> + std::ops::Add<Output = T> + Copy {
+    values[0] * (1.0 - b1 - b2) + values[1] * b1 + values[2] * b2
+}
+
+#[derive(Clone)]
+pub struct Triangle<M: Material + Send + Sync> {
+    vertices: [Point3d; 3],
+    normal: Vec3d,
+    material: M
+}
+
+impl<M: Material + Send + Sync> Triangle<M> {
+    pub fn new(p0: Point3d, p1: Point3d, p2: Point3d, material: M) -> Self {
+        let normal = (p1 - p0).cross(&(p2 - p0)).normalized();
+        Self {
+            vertices: [p0, p1, p2],
+            normal,
+            material
+        }
+    }
+}
+
+impl<M: Material + Send + Sync> Hittable for Triangle<M> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let [p0, p1, p2] = self.vertices;
+        intersect_triangle(ray, p0, p1, p2, t_min, t_max).map(|(t, b1, b2)| {
+            // The barycentric coordinates are the texture coordinates.
+            HitRecord::new_with_face_normal(
+                t, ray.at(t), b1, b2, self.normal, self.material.borrow(), ray
+            ).with_tangents(p1 - p0, p2 - p0)
+        })
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        let [p0, p1, p2] = self.vertices;
+        Some(triangle_bounds(p0, p1, p2))
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: Point3d, direction: Vec3d) -> f64 {
+        let [p0, p1, p2] = self.vertices;
+        let area = 0.5 * (p1 - p0).cross(&(p2 - p0)).norm();
+
+        self.hit(&Ray::new(origin, direction), 0.001, f64::INFINITY)
+            .map_or(0.0, |record| area_pdf_to_solid_angle(&record, direction, area))
+    }
+
+    fn random(&self, origin: Point3d) -> Vec3d {
+        // Uniform point on the triangle by warping the unit square.
+        let (r1, r2) = random_2d();
+        let sqrt_r1 = r1.sqrt();
+        let (b1, b2) = (sqrt_r1 * (1.0 - r2), sqrt_r1 * r2);
+        interpolate(self.vertices, b1, b2) - origin
+    }
+}
+
+// Indices of a mesh face into the buffers of its mesh.
+// Positions, normals and texture coordinates are indexed separately like in Wavefront OBJ files.
+#[derive(Clone, Debug)]
+pub struct MeshFace {
+    pub positions: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>
+}
+
+pub struct TriangleMesh<M: Material + Send + Sync> {
+    positions: Vec<Point3d>,
+    normals: Vec<Vec3d>,
+    uvs: Vec<Point2d>,
+    faces: Vec<MeshFace>,
+    material: M
+}
+
+impl<M: Material + Send + Sync> TriangleMesh<M> {
+    pub fn new(
+        positions: Vec<Point3d>,
+        normals: Vec<Vec3d>,
+        uvs: Vec<Point2d>,
+        faces: Vec<MeshFace>,
+        material: M
+    ) -> Self {
+        for face in &faces {
+            assert!(face.positions.iter().all(|&i| i < positions.len()), "Position index out of bounds.");
+            assert!(face.normals.iter().flatten().all(|&i| i < normals.len()), "Normal index out of bounds.");
+            assert!(face.uvs.iter().flatten().all(|&i| i < uvs.len()), "Texture coordinate index out of bounds.");
+        }
+
+        Self {
+            positions, normals, uvs, faces, material
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.faces.len()
+    }
+
+    pub fn scale(&mut self, factor: f64) {
+        self.positions.iter_mut().for_each(|p| *p *= factor);
+    }
+
+    pub fn set_material(&mut self, material: M) {
+        self.material = material;
+    }
+
+    #[inline]
+    fn vertices(&self, face: &MeshFace) -> (Point3d, Point3d, Point3d) {
+        let [i0, i1, i2] = face.positions;
+        (self.positions[i0], self.positions[i1], self.positions[i2])
+    }
+}
+
+impl<M: Material + Send + Sync + 'static> TriangleMesh<M> {
+    // Splits the mesh into one hittable per face, the buffers stay shared by all faces.
+    pub fn triangles(self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        let mesh = Arc::new(self);
+        (0..mesh.len()).map(|index| {
+            Box::new(MeshTriangle { mesh: mesh.clone(), index }) as Box<dyn Hittable + Send + Sync>
+        }).collect()
+    }
+}
+
+pub struct MeshTriangle<M: Material + Send + Sync> {
+    mesh: Arc<TriangleMesh<M>>,
+    index: usize
+}
+
+impl<M: Material + Send + Sync> Hittable for MeshTriangle<M> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mesh = self.mesh.as_ref();
+        let face = &mesh.faces[self.index];
+        let (p0, p1, p2) = mesh.vertices(face);
+        let (t, b1, b2) = intersect_triangle(ray, p0, p1, p2, t_min, t_max)?;
+
+        let uvs = match face.uvs {
+            Some([i0, i1, i2]) => [mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]],
+            None => [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]
+        };
+        let [(u0, v0), (u1, v1), (u2, v2)] = uvs;
+        let (u, v) = (interpolate([u0, u1, u2], b1, b2), interpolate([v0, v1, v2], b1, b2));
+
+        let geometric_normal = (p1 - p0).cross(&(p2 - p0)).normalized();
+        let mut record = HitRecord::new_with_face_normal(
+            t, ray.at(t), u, v, geometric_normal, mesh.material.borrow(), ray
+        );
+        if let Some((dpdu, dpdv)) = triangle_tangents([p0, p1, p2], uvs) {
+            record = record.with_tangents(dpdu, dpdv);
+        }
+
+        if let Some([i0, i1, i2]) = face.normals {
+            // Smooth shading, the interpolated normal is kept on the side the ray comes from.
+            let shading_normal =
+                interpolate([mesh.normals[i0], mesh.normals[i1], mesh.normals[i2]], b1, b2).normalized();
+            record.normal =
+                if shading_normal.dot(&record.normal) < 0.0 { -shading_normal } else { shading_normal };
+        }
+
+        Some(record)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        let (p0, p1, p2) = self.mesh.vertices(&self.mesh.faces[self.index]);
+        Some(triangle_bounds(p0, p1, p2))
+    }
+}
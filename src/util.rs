@@ -1,7 +1,8 @@
 use std::f64::consts::PI;
-use rand::distributions::Uniform;
-use rand::prelude::*;
+use std::cell::RefCell;
+use rand::{Rng, RngCore};
 use crate::vec3d_extensions::RandomGen;
+use crate::sampler;
 use rand::distributions::uniform::SampleUniform;
 #[macro_export]
 macro_rules! property {
@@ -20,20 +21,105 @@ pub fn rad_to_deg(rad: f64) -> f64 {
     rad * 180.0 / PI
 }
 
-pub fn random_double() -> f64 {
-    lazy_static::lazy_static!{
-        static ref distribution: Uniform<f64> = Uniform::new(0.0, 1.0);
+// PCG32 (XSH RR) after O'Neill, "PCG: A Family of Simple Fast Space-Efficient Statistically
+// Good Algorithms for Random Number Generation".
+#[derive(Clone)]
+pub struct Pcg32 {
+    state: u64,
+    increment: u64
+}
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self { state: 0, increment: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+}
+
+impl RngCore for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.increment);
+        let xor_shifted = (((old >> 18) ^ old) >> 27) as u32;
+        xor_shifted.rotate_right((old >> 59) as u32)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.next_u32() as u64
     }
-    let mut rng = rand::thread_rng();
-    rng.sample(*distribution)
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// Finalizer of SplitMix64, spreads nearby counters over the whole range.
+#[inline]
+pub fn mix_bits(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+thread_local! {
+    static RNG: RefCell<Pcg32> = RefCell::new(Pcg32::new(0, 0));
+}
+
+// Every random number of the current thread comes from here. The renderer restarts the
+// sequence for each sample from the seed of the scene and the index of the sample, so an image
+// does not depend on the threads or the order in which pixels are rendered.
+pub fn seed_random(seed: u64, index: u64) {
+    let rng = Pcg32::new(mix_bits(seed.wrapping_add(mix_bits(index))), 0);
+    RNG.with(|cell| *cell.borrow_mut() = rng);
+}
+
+// Scenes are built from their own stream of the generator, so random scenes and noise textures
+// do not share numbers with any sample of the image.
+pub fn seed_scene_random(seed: u64) {
+    let rng = Pcg32::new(mix_bits(seed), 1);
+    RNG.with(|cell| *cell.borrow_mut() = rng);
+}
+
+#[inline]
+fn with_rng<T>(f: impl FnOnce(&mut Pcg32) -> T) -> T {
+    RNG.with(|cell| f(&mut cell.borrow_mut()))
+}
+
+// Uniform in [0, 1), from the sampler of the pixel when it covers the current dimension or with
+// all 53 bits of the mantissa from the generator.
+pub fn random_double() -> f64 {
+    sampler::next_sample().unwrap_or_else(generator_double)
+}
+
+// A point in [0, 1)², from one pair of dimensions of the sampler. Anything drawing two numbers
+// that belong together, like a position in the pixel or a direction, uses this.
+pub fn random_2d() -> (f64, f64) {
+    sampler::next_sample_2d().unwrap_or_else(|| (generator_double(), generator_double()))
+}
+
+#[inline]
+fn generator_double() -> f64 {
+    with_rng(|rng| (rng.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64))
 }
 
 pub fn random_range(min: f64, max: f64) -> f64 {
     if max - min <= f64::EPSILON {
         min
     } else {
-        let mut rng = rand::thread_rng();
-        rng.gen_range(min, max)
+        min + (max - min) * random_double()
     }
 }
 
@@ -41,8 +127,7 @@ pub fn random_in_range<T: Sized + SampleUniform + Eq>(min: T, max: T) -> T {
     if min == max {
         min
     } else {
-        let mut rng = rand::thread_rng();
-        rng.gen_range(min, max)
+        with_rng(|rng| rng.gen_range(min, max))
     }
 }
 
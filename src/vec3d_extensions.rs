@@ -1,6 +1,7 @@
 use crate::vec3::Vec3d;
-use crate::util::{random_double, random_range};
+use crate::util::{random_double, random_range, random_2d};
 use std::f64::EPSILON;
+use std::f64::consts::{PI, TAU, FRAC_PI_2, FRAC_PI_4};
 use std::ops::Neg;
 
 pub trait RandomGen<E, T> {
@@ -44,19 +45,48 @@ impl Vec3d {
         }
     }
 
+    // Shirley and Chiu's concentric mapping of the square, always two random numbers.
     pub fn random_in_unit_disk() -> Self {
-        loop {
-            let p = Self::new(
-                random_range(-1.0, 1.0),
-                random_range(-1.0, 1.0),
-                0.0
-            );
-            if p.norm_squared() >= 1.0 {
-                continue;
-            }
-
-            return p;
+        let (a, b) = random_2d();
+        let (a, b) = (2.0 * a - 1.0, 2.0 * b - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Self::zero()
         }
+        let (r, phi) = if a.abs() > b.abs() {
+            (a, FRAC_PI_4 * (b / a))
+        } else {
+            (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+        };
+        Self::new(r * phi.cos(), r * phi.sin(), 0.0)
+    }
+
+    // Uniform direction on the unit sphere.
+    pub fn random_unit_vector() -> Self {
+        let (u1, u2) = random_2d();
+        let z = 1.0 - 2.0 * u1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = TAU * u2;
+
+        Self::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    // Cosine weighted direction in the hemisphere around +z.
+    pub fn random_cosine_direction() -> Self {
+        let (r1, r2) = random_2d();
+        let phi = TAU * r1;
+        let r = r2.sqrt();
+
+        Self::new(r * phi.cos(), r * phi.sin(), (1.0 - r2).sqrt())
+    }
+
+    // Uniform direction in the cone around +z whose half angle has the cosine `cos_theta_max`.
+    pub fn random_in_cone(cos_theta_max: f64) -> Self {
+        let (u1, u2) = random_2d();
+        let z = 1.0 + u1 * (cos_theta_max - 1.0);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = TAU * u2;
+
+        Self::new(r * phi.cos(), r * phi.sin(), z)
     }
 
     pub fn near_zero(&self) -> bool {
@@ -76,6 +106,64 @@ impl Vec3d {
     }
 }
 
+pub fn uniform_sphere_pdf() -> f64 {
+    1.0 / (4.0 * PI)
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    if cos_theta > 0.0 { cos_theta / PI } else { 0.0 }
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: f64) -> f64 {
+    1.0 / (TAU * (1.0 - cos_theta_max))
+}
+
+// Right handed orthonormal basis whose w axis is a given direction.
+#[derive(Copy, Clone, Debug)]
+pub struct OrthonormalBasis {
+    pub u: Vec3d,
+    pub v: Vec3d,
+    pub w: Vec3d
+}
+
+impl OrthonormalBasis {
+    // `w` has to be normalized. Uses the branchless construction of Duff et al.
+    pub fn from_w(w: Vec3d) -> Self {
+        let sign = 1.0f64.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+
+        Self {
+            u: Vec3d::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x),
+            v: Vec3d::new(b, sign + w.y * w.y * a, -w.y),
+            w
+        }
+    }
+
+    // `w` has to be normalized, `u` is made perpendicular to it. Falls back to `from_w` when `u` is
+    // parallel to `w`.
+    pub fn from_w_u(w: Vec3d, u: Vec3d) -> Self {
+        let u = u - w * w.dot(&u);
+        if u.near_zero() {
+            return Self::from_w(w)
+        }
+        let u = u.normalized();
+        Self { u, v: w.cross(&u), w }
+    }
+
+    // From coordinates in this basis to world coordinates.
+    #[inline]
+    pub fn local(&self, a: Vec3d) -> Vec3d {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    // From world coordinates to coordinates in this basis.
+    #[inline]
+    pub fn world_to_local(&self, a: Vec3d) -> Vec3d {
+        Vec3d::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
+
 impl std::iter::Sum for Vec3d {
     fn sum<I: Iterator<Item=Self>>(iter: I) -> Self {
         iter.fold(Vec3d::zero(), std::ops::Add::add)
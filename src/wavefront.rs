@@ -0,0 +1,563 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use image::{DynamicImage, ImageError};
+
+use crate::vec3::{Point3d, Vec3d};
+use crate::color::Color3d;
+use crate::hittable::Hittable;
+use crate::material::{Material, Diffuse, Metal, Dielectric, DiffuseLight};
+use crate::texture::SolidColor;
+use crate::image_texture::ImageTexture;
+use crate::triangle::{TriangleMesh, MeshFace};
+use crate::acceleration::bvh::OwnedBVH;
+
+// Loader of Wavefront OBJ models and their MTL material libraries.
+//
+// Supported OBJ statements are `v`, `vn`, `vt`, `f` (polygons are triangulated as fans),
+// `g`, `o`, `usemtl` and `mtllib`. Other statements are ignored. Names of materials and of the
+// one library of a `mtllib` are the rest of the line and may contain spaces.
+// Materials are mapped onto the materials of this renderer:
+//   `Ke` (emission)                        -> DiffuseLight
+//   `illum` 4, 6, 7 or `d` < 1 with `Ni`   -> Dielectric
+//   `illum` 3 or `Ks` brighter than `Kd`   -> Metal, fuzz derived from `Ns`
+//   otherwise `Kd` or `map_Kd`             -> Diffuse
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    Syntax { file: PathBuf, line: usize, message: String },
+    Texture(PathBuf, ImageError),
+    NoFaces(PathBuf)
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::Io(file, error) => write!(f, "{}: {}", file.display(), error),
+            ObjError::Syntax { file, line, message } =>
+                write!(f, "{}: line {}: {}", file.display(), line, message),
+            ObjError::Texture(file, error) => write!(f, "{}: {}", file.display(), error),
+            ObjError::NoFaces(file) => write!(f, "{}: no faces", file.display())
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+type ObjResult<T> = Result<T, ObjError>;
+
+pub type ObjMesh = TriangleMesh<Box<dyn Material + Send + Sync>>;
+
+// One mesh per group and material of the file.
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>
+}
+
+impl ObjModel {
+    pub fn load<P: AsRef<Path>>(path: P) -> ObjResult<Self> {
+        let path = path.as_ref();
+        let source = read_file(path)?;
+
+        ObjParser::new(path).parse(&source)
+    }
+
+    pub fn scale(&mut self, factor: f64) {
+        self.meshes.iter_mut().for_each(|mesh| mesh.scale(factor));
+    }
+
+    // Replaces the materials of the file, e.g. when the model comes without a material library.
+    pub fn set_material<F>(&mut self, material: F)
+    where F: Fn() -> Box<dyn Material + Send + Sync> {
+        self.meshes.iter_mut().for_each(|mesh| mesh.set_material(material()));
+    }
+
+    pub fn into_hittables(self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        self.meshes.into_iter().flat_map(TriangleMesh::triangles).collect()
+    }
+
+    pub fn into_bvh(self, time0: f64, time1: f64) -> OwnedBVH {
+        OwnedBVH::new(self.into_hittables(), time0, time1)
+    }
+}
+
+fn read_file(path: &Path) -> ObjResult<String> {
+    std::fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))
+}
+
+struct Statement<'a> {
+    file: &'a Path,
+    line: usize,
+    keyword: &'a str,
+    arguments: Vec<&'a str>,
+    // Everything after the keyword, spaces included.
+    rest: &'a str
+}
+
+impl<'a> Statement<'a> {
+    fn parse(file: &'a Path, line: usize, text: &'a str) -> Option<Self> {
+        let text = text.split('#').next().unwrap_or("").trim();
+        let mut tokens = text.split_whitespace();
+        let keyword = tokens.next()?;
+        let rest = text[keyword.len()..].trim_start();
+
+        Some(Self { file, line, keyword, arguments: tokens.collect(), rest })
+    }
+
+    fn error<T>(&self, message: String) -> ObjResult<T> {
+        Err(ObjError::Syntax { file: self.file.to_path_buf(), line: self.line, message })
+    }
+
+    // The rest of the line, names may contain spaces.
+    fn name(&self) -> ObjResult<String> {
+        if self.arguments.is_empty() {
+            self.error(format!("`{}` expects a name", self.keyword))
+        } else {
+            Ok(self.rest.to_string())
+        }
+    }
+
+    fn numbers(&self, min: usize, max: usize) -> ObjResult<Vec<f64>> {
+        if self.arguments.len() < min || self.arguments.len() > max {
+            return self.error(format!("`{}` expects {} to {} numbers, found {}",
+                                      self.keyword, min, max, self.arguments.len()));
+        }
+        self.arguments.iter().map(|value| {
+            value.parse().or_else(|_| self.error(format!("expected a number, found `{}`", value)))
+        }).collect()
+    }
+
+    fn vector(&self) -> ObjResult<Vec3d> {
+        let numbers = self.numbers(3, 4)?;
+        Ok(Vec3d::new(numbers[0], numbers[1], numbers[2]))
+    }
+
+    // Three components, or a single one for a gray.
+    fn color(&self) -> ObjResult<Color3d> {
+        match self.numbers(1, 3)?.as_slice() {
+            [gray] => Ok(Color3d::only(*gray)),
+            [r, g, b] => Ok(Color3d::new(*r, *g, *b)),
+            _ => self.error(format!("`{}` expects 1 or 3 numbers", self.keyword))
+        }
+    }
+
+    fn number(&self) -> ObjResult<f64> {
+        Ok(self.numbers(1, 1)?[0])
+    }
+}
+
+#[derive(Copy, Clone)]
+struct VertexIndex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>
+}
+
+#[derive(Default)]
+struct GroupBuilder {
+    material: Option<String>,
+    positions: Vec<Point3d>,
+    normals: Vec<Vec3d>,
+    uvs: Vec<(f64, f64)>,
+    faces: Vec<MeshFace>,
+    // Indices in the file to indices in this group, groups only keep the vertices they use.
+    position_map: HashMap<usize, usize>,
+    normal_map: HashMap<usize, usize>,
+    uv_map: HashMap<usize, usize>
+}
+
+fn remap<T: Copy>(index: usize, source: &[T], target: &mut Vec<T>, map: &mut HashMap<usize, usize>) -> usize {
+    *map.entry(index).or_insert_with(|| {
+        target.push(source[index]);
+        target.len() - 1
+    })
+}
+
+struct ObjParser<'a> {
+    file: &'a Path,
+    positions: Vec<Point3d>,
+    normals: Vec<Vec3d>,
+    uvs: Vec<(f64, f64)>,
+    materials: HashMap<String, MtlMaterial>,
+    groups: Vec<GroupBuilder>,
+    current: GroupBuilder
+}
+
+impl<'a> ObjParser<'a> {
+    fn new(file: &'a Path) -> Self {
+        Self {
+            file,
+            positions: vec![],
+            normals: vec![],
+            uvs: vec![],
+            materials: HashMap::new(),
+            groups: vec![],
+            current: GroupBuilder::default()
+        }
+    }
+
+    fn parse(mut self, source: &str) -> ObjResult<ObjModel> {
+        for (index, line) in source.lines().enumerate() {
+            if let Some(statement) = Statement::parse(self.file, index + 1, line) {
+                self.statement(&statement)?;
+            }
+        }
+        self.next_group(None);
+        if self.groups.is_empty() {
+            return Err(ObjError::NoFaces(self.file.to_path_buf()));
+        }
+
+        let materials = self.materials;
+        let meshes = self.groups.into_iter().map(|group| {
+            let material: Box<dyn Material + Send + Sync> = match &group.material {
+                Some(name) => materials.get(name).map_or_else(MtlMaterial::fallback, MtlMaterial::build),
+                None => MtlMaterial::fallback()
+            };
+            TriangleMesh::new(group.positions, group.normals, group.uvs, group.faces, material)
+        }).collect();
+
+        Ok(ObjModel { meshes })
+    }
+
+    fn statement(&mut self, statement: &Statement) -> ObjResult<()> {
+        match statement.keyword {
+            "v" => self.positions.push(statement.vector()?),
+            "vn" => self.normals.push(statement.vector()?),
+            "vt" => {
+                let uv = statement.numbers(1, 3)?;
+                self.uvs.push((uv[0], uv.get(1).copied().unwrap_or(0.0)));
+            }
+            "f" => self.face(statement)?,
+            "g" | "o" => {
+                let material = self.current.material.clone();
+                self.next_group(material);
+            }
+            // Materials missing from the libraries get the default material.
+            "usemtl" => {
+                let name = statement.name()?;
+                self.next_group(Some(name));
+            }
+            "mtllib" => {
+                let path = self.file.parent().unwrap_or_else(|| Path::new("")).join(statement.name()?);
+                self.materials.extend(parse_mtl(&path)?);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn next_group(&mut self, material: Option<String>) {
+        let group = std::mem::replace(&mut self.current, GroupBuilder {
+            material, ..GroupBuilder::default()
+        });
+        if !group.faces.is_empty() {
+            self.groups.push(group);
+        }
+    }
+
+    // OBJ indices start from 1, negative indices count backwards from the last element.
+    fn resolve(statement: &Statement, token: &str, count: usize) -> ObjResult<usize> {
+        let index: i64 = token.parse()
+            .or_else(|_| statement.error(format!("invalid index `{}`", token)))?;
+        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+        if resolved < 0 || resolved >= count as i64 {
+            statement.error(format!("index {} out of range, {} element(s) defined", index, count))
+        } else {
+            Ok(resolved as usize)
+        }
+    }
+
+    fn vertex(&self, statement: &Statement, token: &str) -> ObjResult<VertexIndex> {
+        let mut parts = token.split('/');
+        let position = Self::resolve(statement, parts.next().unwrap_or(""), self.positions.len())?;
+        let uv = match parts.next() {
+            Some("") | None => None,
+            Some(uv) => Some(Self::resolve(statement, uv, self.uvs.len())?)
+        };
+        let normal = match parts.next() {
+            Some("") | None => None,
+            Some(normal) => Some(Self::resolve(statement, normal, self.normals.len())?)
+        };
+        if parts.next().is_some() {
+            return statement.error(format!("malformed face vertex `{}`", token));
+        }
+
+        Ok(VertexIndex { position, uv, normal })
+    }
+
+    fn face(&mut self, statement: &Statement) -> ObjResult<()> {
+        if statement.arguments.len() < 3 {
+            return statement.error("a face needs at least 3 vertices".to_string());
+        }
+        let vertices = statement.arguments.iter()
+            .map(|token| self.vertex(statement, token))
+            .collect::<ObjResult<Vec<_>>>()?;
+
+        let ObjParser { positions, normals, uvs, current: group, .. } = self;
+        let vertices: Vec<_> = vertices.into_iter().map(|vertex| VertexIndex {
+            position: remap(vertex.position, positions, &mut group.positions, &mut group.position_map),
+            uv: vertex.uv.map(|uv| remap(uv, uvs, &mut group.uvs, &mut group.uv_map)),
+            normal: vertex.normal.map(|normal| remap(normal, normals, &mut group.normals, &mut group.normal_map))
+        }).collect();
+
+        for i in 1..vertices.len() - 1 {
+            let (a, b, c) = (vertices[0], vertices[i], vertices[i + 1]);
+            // Normals and uvs are only used when all three vertices have them.
+            group.faces.push(MeshFace {
+                positions: [a.position, b.position, c.position],
+                uvs: a.uv.and_then(|a| Some([a, b.uv?, c.uv?])),
+                normals: a.normal.and_then(|a| Some([a, b.normal?, c.normal?]))
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct MtlMaterial {
+    diffuse: Color3d,
+    specular: Color3d,
+    emission: Color3d,
+    shininess: f64,
+    index_refraction: f64,
+    dissolve: f64,
+    illumination: u32,
+    diffuse_map: Option<ImageTexture<DynamicImage>>
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Color3d::only(0.8),
+            specular: Color3d::zero(),
+            emission: Color3d::zero(),
+            shininess: 0.0,
+            index_refraction: 1.0,
+            dissolve: 1.0,
+            illumination: 2,
+            diffuse_map: None
+        }
+    }
+}
+
+fn max_component(color: Color3d) -> f64 {
+    color.x.max(color.y).max(color.z)
+}
+
+impl MtlMaterial {
+    fn fallback() -> Box<dyn Material + Send + Sync> {
+        Self::default().build()
+    }
+
+    fn build(&self) -> Box<dyn Material + Send + Sync> {
+        let transparent = matches!(self.illumination, 4 | 6 | 7) || self.dissolve < 1.0;
+        let metallic = self.illumination == 3 || max_component(self.specular) > max_component(self.diffuse);
+
+        if max_component(self.emission) > 0.0 {
+            Box::new(DiffuseLight::new(SolidColor::new(self.emission)))
+        } else if transparent && self.index_refraction > 1.0 {
+            Box::new(Dielectric::new(self.index_refraction))
+        } else if metallic && self.diffuse_map.is_none() {
+            // Map the Phong exponent onto fuzziness, a rough surface has a small exponent.
+            let fuzz = (2.0 / (self.shininess + 2.0)).sqrt();
+            Box::new(Metal { albedo: self.specular, fuzz })
+        } else if let Some(texture) = &self.diffuse_map {
+            Box::new(Diffuse::new(texture.clone()))
+        } else {
+            Box::new(Diffuse::for_color(self.diffuse))
+        }
+    }
+}
+
+fn parse_mtl(path: &Path) -> ObjResult<HashMap<String, MtlMaterial>> {
+    let source = read_file(path)?;
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let statement = match Statement::parse(path, index + 1, line) {
+            Some(statement) => statement,
+            None => continue
+        };
+        if statement.keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((statement.name()?, MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some((_, material)) => material,
+            None => return statement.error(format!("`{}` before any `newmtl`", statement.keyword))
+        };
+        match statement.keyword {
+            "Kd" => material.diffuse = statement.color()?,
+            "Ks" => material.specular = statement.color()?,
+            "Ke" => material.emission = statement.color()?,
+            "Ns" => material.shininess = statement.number()?,
+            "Ni" => material.index_refraction = statement.number()?,
+            "d" => material.dissolve = statement.number()?,
+            "Tr" => material.dissolve = 1.0 - statement.number()?,
+            "illum" => material.illumination = statement.number()? as u32,
+            "map_Kd" => {
+                // Texture options come before the file name.
+                let file = match statement.arguments.last() {
+                    Some(file) => path.parent().unwrap_or_else(|| Path::new("")).join(file),
+                    None => return statement.error("`map_Kd` expects a file name".to_string())
+                };
+                let texture = ImageTexture::from_file(file.to_string_lossy().to_string())
+                    .map_err(|e| ObjError::Texture(file, e))?;
+                material.diffuse_map = Some(texture);
+            }
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    // The groups of a file, before they are turned into meshes.
+    fn parse_groups(source: &str) -> Vec<GroupBuilder> {
+        let mut parser = ObjParser::new(Path::new("test.obj"));
+        for (index, line) in source.lines().enumerate() {
+            if let Some(statement) = Statement::parse(parser.file, index + 1, line) {
+                parser.statement(&statement).unwrap();
+            }
+        }
+        parser.next_group(None);
+        parser.groups
+    }
+
+    fn face_positions(group: &GroupBuilder) -> Vec<[Point3d; 3]> {
+        group.faces.iter().map(|face| face.positions.map(|i| group.positions[i])).collect()
+    }
+
+    const SQUARE: &str = "
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+    ";
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_element() {
+        let source = format!("{}
+            vt 0 0
+            vt 1 0
+            vt 1 1
+            vn 0 0 1
+            f -4/-3/-1 -3/-2/-1 -2/-1/-1
+            v 5 5 5
+            f 1 -1 2
+        ", SQUARE);
+        let groups = parse_groups(&source);
+        let group = &groups[0];
+        let p = |x, y, z| Vec3d::new(x, y, z);
+        assert_eq!(face_positions(group), vec![
+            [p(0.0, 0.0, 0.0), p(1.0, 0.0, 0.0), p(1.0, 1.0, 0.0)],
+            [p(0.0, 0.0, 0.0), p(5.0, 5.0, 5.0), p(1.0, 0.0, 0.0)]
+        ]);
+        let uvs = group.faces[0].uvs.unwrap().map(|i| group.uvs[i]);
+        assert_eq!(uvs, [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)]);
+        let normals = group.faces[0].normals.unwrap().map(|i| group.normals[i]);
+        assert_eq!(normals, [p(0.0, 0.0, 1.0); 3]);
+
+        let error = |face: &str| {
+            let source = format!("{}\n{}", SQUARE, face);
+            ObjParser::new(Path::new("test.obj")).parse(&source).err().map(|e| e.to_string())
+        };
+        let out_of_range = |index| Some(format!("test.obj: line 7: index {} out of range, 4 element(s) defined", index));
+        assert_eq!(error("f 1 2 -5"), out_of_range(-5));
+        assert_eq!(error("f 1 2 0"), out_of_range(0));
+        assert_eq!(error("f 1 2 5"), out_of_range(5));
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let groups = parse_groups(&format!("{}\nv 0.5 1.5 0\nf 1 2 3 5 4", SQUARE));
+        let p = |x, y| Vec3d::new(x, y, 0.0);
+        assert_eq!(face_positions(&groups[0]), vec![
+            [p(0.0, 0.0), p(1.0, 0.0), p(1.0, 1.0)],
+            [p(0.0, 0.0), p(1.0, 1.0), p(0.5, 1.5)],
+            [p(0.0, 0.0), p(0.5, 1.5), p(0.0, 1.0)]
+        ]);
+    }
+
+    #[test]
+    fn normals_and_uvs_are_kept_only_when_every_vertex_has_them() {
+        let source = format!("{}
+            vt 0 0
+            vn 0 0 1
+            f 1/1/1 2/1/1 3/1/1
+            f 1//1 2//1 3//1
+            f 1/1 2/1 3/1
+            f 1/1/1 2/1 3/1/1
+            f 1 2 3
+        ", SQUARE);
+        let groups = parse_groups(&source);
+        let present: Vec<_> = groups[0].faces.iter()
+            .map(|face| (face.uvs.is_some(), face.normals.is_some()))
+            .collect();
+        assert_eq!(present, vec![(true, true), (false, true), (true, false), (true, false), (false, false)]);
+        // Vertices without normals or uvs don't pull unused ones into the group.
+        let groups = parse_groups(&format!("{}\nvt 0 0\nvn 0 0 1\nf 1 2 3", SQUARE));
+        assert!(groups[0].uvs.is_empty() && groups[0].normals.is_empty());
+    }
+
+    // The emission of the face hit at (x + 0.25, 0.25).
+    fn emission_at(triangles: &[Box<dyn Hittable + Send + Sync>], x: f64) -> Color3d {
+        let ray = Ray::new(Point3d::new(x + 0.25, 0.25, -1.0), Vec3d::new(0.0, 0.0, 1.0));
+        let record = triangles.iter().find_map(|triangle| triangle.hit(&ray, 0.001, f64::INFINITY)).unwrap();
+        record.material.emitted(&record, -ray.direction())
+    }
+
+    #[test]
+    fn usemtl_picks_materials_from_a_library_with_spaces_in_its_name() {
+        let dir = std::env::temp_dir().join(format!("wavefront_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("warm and  cool.mtl"), "
+            newmtl warm glow
+            Ke 3 0 0
+            newmtl cool
+            Ke 0 0 2
+        ").unwrap();
+        std::fs::write(dir.join("lamps.obj"), "
+            mtllib warm and  cool.mtl
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            v 10 0 0
+            v 11 0 0
+            v 10 1 0
+            v 20 0 0
+            v 21 0 0
+            v 20 1 0
+            usemtl warm glow
+            f 1 2 3
+            usemtl cool
+            f 4 5 6
+            usemtl unknown
+            f 7 8 9
+        ").unwrap();
+        let model = ObjModel::load(dir.join("lamps.obj"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let model = model.unwrap();
+        assert_eq!(model.meshes.len(), 3);
+        let triangles = model.into_hittables();
+        assert_eq!(emission_at(&triangles, 0.0), Color3d::new(3.0, 0.0, 0.0));
+        assert_eq!(emission_at(&triangles, 10.0), Color3d::new(0.0, 0.0, 2.0));
+        assert_eq!(emission_at(&triangles, 20.0), Color3d::zero());
+    }
+}